@@ -0,0 +1,124 @@
+use super::lock::SpinLock;
+use super::{FlowState, Reset, State};
+use core::fmt;
+use core::task::Waker;
+use heapless::Vec;
+use portable_atomic::{AtomicU32, Ordering};
+
+/// Why a control command did not take effect
+#[derive(Debug, Clone, PartialEq)]
+pub enum CommandError<ST = FlowState> {
+    /// the control channel was full, the command was never sent
+    Full,
+    /// the flow cannot make this transition from the state it was in
+    Rejected(ST),
+    /// the flow ended before it got to the command
+    Ended,
+    /// the flow got to the command, but newer commands pushed its result out before it was read
+    Lost,
+}
+
+impl<ST: fmt::Debug> fmt::Display for CommandError<ST> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CommandError::Full => write!(f, "control channel is full"),
+            CommandError::Rejected(state) => write!(f, "command rejected in state {:?}", state),
+            CommandError::Ended => write!(f, "flow ended before the command was applied"),
+            CommandError::Lost => write!(f, "the result of the command was overwritten"),
+        }
+    }
+}
+
+impl<ST: fmt::Debug> core::error::Error for CommandError<ST> {}
+
+/// The sequence number of a consumed command with its result, the state it was rejected in
+type Ack<ST> = (u32, Result<(), ST>);
+
+/// Results of the most recent acknowledged commands, indexed by sequence number
+/// A result is kept until `N` newer commands have been consumed
+pub struct Acks<ST, const N: usize> {
+    next_seq: AtomicU32,
+    ring: SpinLock<[Option<Ack<ST>>; N]>,
+    /// the tasks waiting on a command, by its sequence number
+    /// only the up to `N` commands still in the control channel wait, older entries are stale
+    wakers: SpinLock<Vec<(u32, Waker), N>>,
+}
+
+impl<ST, const N: usize> Default for Acks<ST, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<ST, const N: usize> Acks<ST, N> {
+    pub const fn new() -> Self {
+        Acks {
+            next_seq: AtomicU32::new(1),
+            ring: SpinLock::new([const { None }; N]),
+            wakers: SpinLock::new(Vec::new()),
+        }
+    }
+}
+
+impl<ST, const N: usize> Reset for Acks<ST, N> {
+    fn reset(&self) {
+        self.ring.lock().fill_with(|| None);
+    }
+}
+
+impl<ST: State, const N: usize> Acks<ST, N> {
+    /// Hand out a sequence number for a command that wants an acknowledgement
+    /// 0 is reserved for events nobody waits on
+    pub fn issue(&self) -> u32 {
+        loop {
+            let seq = self.next_seq.fetch_add(1, Ordering::Relaxed);
+            if seq != 0 {
+                return seq;
+            }
+        }
+    }
+
+    /// used by the flow future once it applied or rejected a command
+    /// `state` is the state the flow was left in by the command
+    pub fn record(&self, seq: u32, accepted: bool, state: &ST) {
+        let result = if accepted { Ok(()) } else { Err(state.clone()) };
+        self.ring.lock()[seq as usize % N] = Some((seq, result));
+    }
+
+    /// The result of a command, if it has been consumed yet
+    pub fn check(&self, seq: u32) -> Option<Result<(), CommandError<ST>>> {
+        let ring = self.ring.lock();
+        let (recorded, result) = ring[seq as usize % N].as_ref()?;
+        if (recorded.wrapping_sub(seq) as i32) < 0 {
+            return None;
+        }
+        if *recorded != seq {
+            // a newer command took over the entry, ours was consumed but its result is gone
+            return Some(Err(CommandError::Lost));
+        }
+        Some(result.clone().map_err(CommandError::Rejected))
+    }
+
+    /// Wake `waker` once the flow published the outcome of command `seq`
+    pub fn register(&self, seq: u32, waker: &Waker) {
+        let mut wakers = self.wakers.lock();
+        if let Some((_, registered)) = wakers.iter_mut().find(|(at, _)| *at == seq) {
+            registered.clone_from(waker);
+            return;
+        }
+        if wakers.is_full() {
+            // the oldest entry belongs to a command that is done or dropped by now,
+            // if it still waits it checks again and registers anew
+            wakers.remove(0).1.wake();
+        }
+        let _ = wakers.push((seq, waker.clone()));
+    }
+
+    /// used by the flow future once the outcome of the commands it consumed is observable
+    pub fn wake(&self) {
+        let wakers = core::mem::take(&mut *self.wakers.lock());
+        for (_, waker) in wakers {
+            waker.wake();
+        }
+    }
+}
@@ -0,0 +1,516 @@
+use super::control::Ticket;
+use super::flow::name_of;
+use super::{
+    BaseController, Flow, FlowEvent, FlowEventHandler, FlowOutcome, FlowOutput, FlowSlot,
+    FlowState, FnController, FnDataHandle, Handler, JOURNAL_CAPACITY, OutcomeOf, Reply, Shared,
+    State, UserControlEvent, UserController, UserDataHandle,
+};
+use crate::runtime::FlowRuntime;
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+
+/// The flow of a member, boxed so members can run different functions with the same output
+type MemberFlow<O> = Pin<Box<dyn Future<Output = OutcomeOf<O>> + Send>>;
+
+/// One flow of a composite, see `sequence`, `join_all` and `select`
+/// `O` is the output of its function, members of one composite can run different functions
+/// as long as they return the same type
+pub struct Member<
+    O: FlowOutput,
+    U: 'static,
+    const CHAN_N: usize,
+    Q: 'static = (),
+    ST: State = FlowState,
+    H: 'static = FlowEventHandler,
+    const JOURNAL_N: usize = JOURNAL_CAPACITY,
+> {
+    /// dropped once the flow ended
+    flow: Option<MemberFlow<O>>,
+    ctrl: UserController<U, CHAN_N, Q, ST, H, JOURNAL_N>,
+    outcome: Option<OutcomeOf<O>>,
+    /// whether the composite polls the flow yet, a sequence starts one member after the other
+    started: bool,
+    /// handed an input since its last poll, its state does not show it yet
+    fed: bool,
+}
+
+impl<
+    O: FlowOutput,
+    U: 'static,
+    const CHAN_N: usize,
+    Q: 'static,
+    ST: State,
+    H: 'static,
+    const JOURNAL_N: usize,
+> Member<O, U, CHAN_N, Q, ST, H, JOURNAL_N>
+where
+    H: Handler<ST, FlowEvent<U>, Output = Reply<U>>,
+{
+    /// Set up `function` to run with `init` in `slot`, the data handle is the user's end of its channel
+    /// The controller of the member stays reachable through the slot, e.g. `ArcSlot::user`
+    #[allow(clippy::type_complexity)]
+    pub fn new<R, Fun, I, F, S, UD, FD, const DATA_N: usize>(
+        runtime: &R,
+        function: Fun,
+        init: I,
+        slot: S,
+    ) -> (Self, UserDataHandle<UD, FD, DATA_N>)
+    where
+        R: FlowRuntime,
+        Fun: FnOnce(
+            I,
+            FnController<R, U, CHAN_N, Q, ST, H, JOURNAL_N>,
+            FnDataHandle<UD, FD, DATA_N>,
+        ) -> F,
+        F: Future<Output = O>,
+        Flow<F, U, CHAN_N, Q, ST, H, JOURNAL_N>: Send + 'static,
+        S: FlowSlot<U, UD, FD, CHAN_N, DATA_N, Q, ST, H, JOURNAL_N>,
+        UD: 'static,
+        FD: 'static,
+    {
+        let ((fn_ctrl, flow_ctrl, user_ctrl), (fn_data, user_data)) = slot.parts(runtime);
+        let flow =
+            Flow::new(function(init, fn_ctrl, fn_data), flow_ctrl).with_name(name_of::<Fun>());
+        let member = Self {
+            flow: Some(Box::pin(flow)),
+            ctrl: user_ctrl,
+            outcome: None,
+            started: false,
+            fed: false,
+        };
+        (member, user_data)
+    }
+
+    fn is_live(&self) -> bool {
+        self.started && self.outcome.is_none()
+    }
+
+    /// used by the composite to poll a started member, true once it ended with this poll
+    fn poll_flow(&mut self, cx: &mut Context<'_>) -> bool {
+        let Some(flow) = self.flow.as_mut().filter(|_| self.started) else {
+            return false;
+        };
+        self.fed = false;
+        match flow.as_mut().poll(cx) {
+            Poll::Ready(outcome) => {
+                self.outcome = Some(outcome);
+                self.flow = None;
+                true
+            }
+            Poll::Pending => false,
+        }
+    }
+
+    /// The cancel to wait for, `None` if the member had ended or was dropped right away
+    fn cancel(&mut self) -> Option<Ticket> {
+        self.started = true;
+        if self.outcome.is_some() {
+            return None;
+        }
+        let ticket = self.ctrl.cancel().ticket();
+        if ticket.is_none() {
+            // the flow is dropped as it is, nobody will apply the cancel
+            self.flow = None;
+            self.outcome = Some(FlowOutcome::Cancelled);
+        }
+        ticket
+    }
+
+    /// Whether the member applied the command, once it consumed it or can no longer do so
+    fn applied(&self, ticket: Ticket) -> Option<bool> {
+        match self.ctrl.command_result(ticket) {
+            Some(result) => Some(result.is_ok()),
+            // dropped without ever publishing its end
+            None if self.flow.is_none() => Some(false),
+            None => None,
+        }
+    }
+}
+
+/// A command of the user passed on to the members, acknowledged once each of them applied or
+/// rejected it, accepted if any of them applied it
+struct Relayed {
+    seq: u32,
+    /// the members that have not consumed it yet, by index
+    tickets: Vec<(usize, Ticket)>,
+    accepted: bool,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Mode {
+    Sequence,
+    JoinAll,
+    Select,
+}
+
+/// Several flows run as one, controlled through a single `UserController`
+/// Pause, resume, cancel and signals go to every member that runs, input to the member that waits on it.
+/// Those commands resolve once every member they went to applied or rejected them.
+/// The composite is `Blocked` while any member is, `Paused` once all running members are and ends as
+/// `Completed` once the members completed, as `Error` if one failed, else as `Cancelled`.
+/// It resolves to the outcome of every member, in order. Members it cancelled end as `Cancelled`.
+///
+/// ```ignore
+/// let (a, _) = Member::new(&runtime, fetch, "a", ArcSlot::new());
+/// let (b, _) = Member::new(&runtime, fetch_cached, "b", ArcSlot::new());
+/// let (both, ctrl) = join_all([a, b]);
+/// runtime.spawn(async move { both.await; });
+/// ctrl.pause().await?;
+/// ```
+pub struct Composite<
+    O: FlowOutput,
+    U: 'static,
+    const CHAN_N: usize,
+    Q: 'static = (),
+    ST: State = FlowState,
+    H: 'static = FlowEventHandler,
+    const JOURNAL_N: usize = JOURNAL_CAPACITY,
+> {
+    ctrl: Shared<BaseController<U, CHAN_N, Q>>,
+    mode: Mode,
+    members: Vec<Member<O, U, CHAN_N, Q, ST, H, JOURNAL_N>>,
+    state: FlowState,
+    /// paused by the user, a sequence starts its next member once it is resumed
+    paused: bool,
+    /// commands passed on to the members they have not all consumed yet
+    relayed: Vec<Relayed>,
+}
+
+// the members are boxed, the outcomes are never pinned
+impl<O: FlowOutput, U, const CHAN_N: usize, Q, ST: State, H, const JOURNAL_N: usize> Unpin
+    for Composite<O, U, CHAN_N, Q, ST, H, JOURNAL_N>
+{
+}
+
+/// Run the members one after the other, each once the previous one completed
+/// A member that does not complete ends the sequence, the members after it are cancelled
+#[allow(clippy::type_complexity)]
+pub fn sequence<O, U, const CHAN_N: usize, Q, ST, H, const JOURNAL_N: usize>(
+    members: impl IntoIterator<Item = Member<O, U, CHAN_N, Q, ST, H, JOURNAL_N>>,
+) -> (
+    Composite<O, U, CHAN_N, Q, ST, H, JOURNAL_N>,
+    UserController<U, CHAN_N, Q>,
+)
+where
+    O: FlowOutput,
+    ST: State,
+    H: Handler<ST, FlowEvent<U>, Output = Reply<U>>,
+{
+    Composite::new(Mode::Sequence, members)
+}
+
+/// Run the members side by side until all of them completed
+/// A member that does not complete ends the composite, the others are cancelled
+#[allow(clippy::type_complexity)]
+pub fn join_all<O, U, const CHAN_N: usize, Q, ST, H, const JOURNAL_N: usize>(
+    members: impl IntoIterator<Item = Member<O, U, CHAN_N, Q, ST, H, JOURNAL_N>>,
+) -> (
+    Composite<O, U, CHAN_N, Q, ST, H, JOURNAL_N>,
+    UserController<U, CHAN_N, Q>,
+)
+where
+    O: FlowOutput,
+    ST: State,
+    H: Handler<ST, FlowEvent<U>, Output = Reply<U>>,
+{
+    Composite::new(Mode::JoinAll, members)
+}
+
+/// Run the members side by side until one of them completes or fails, the others are cancelled
+/// Members cancelled through their own controller drop out of the race
+#[allow(clippy::type_complexity)]
+pub fn select<O, U, const CHAN_N: usize, Q, ST, H, const JOURNAL_N: usize>(
+    members: impl IntoIterator<Item = Member<O, U, CHAN_N, Q, ST, H, JOURNAL_N>>,
+) -> (
+    Composite<O, U, CHAN_N, Q, ST, H, JOURNAL_N>,
+    UserController<U, CHAN_N, Q>,
+)
+where
+    O: FlowOutput,
+    ST: State,
+    H: Handler<ST, FlowEvent<U>, Output = Reply<U>>,
+{
+    Composite::new(Mode::Select, members)
+}
+
+impl<
+    O: FlowOutput,
+    U: 'static,
+    const CHAN_N: usize,
+    Q: 'static,
+    ST: State,
+    H: 'static,
+    const JOURNAL_N: usize,
+> Composite<O, U, CHAN_N, Q, ST, H, JOURNAL_N>
+where
+    H: Handler<ST, FlowEvent<U>, Output = Reply<U>>,
+{
+    fn new(
+        mode: Mode,
+        members: impl IntoIterator<Item = Member<O, U, CHAN_N, Q, ST, H, JOURNAL_N>>,
+    ) -> (Self, UserController<U, CHAN_N, Q>) {
+        let ctrl = Shared::Arc(Arc::new(BaseController::default()));
+        let mut members: Vec<_> = members.into_iter().collect();
+        for (index, member) in members.iter_mut().enumerate() {
+            member.started = mode != Mode::Sequence || index == 0;
+        }
+        let composite = Self {
+            ctrl: ctrl.clone(),
+            mode,
+            members,
+            state: FlowState::Running,
+            paused: false,
+            relayed: Vec::new(),
+        };
+        (composite, UserController::new(ctrl))
+    }
+
+    /// used by the poll for every event sent to the composite, see `BaseController::relay`
+    /// pause, resume and cancel go to every member whatever the composite's derived state is
+    fn forward(&mut self, seq: u32, event: FlowEvent<U>) -> Option<bool> {
+        let FlowEvent::User(event) = event else {
+            return Some(false);
+        };
+        let (tickets, accepted) = match event {
+            UserControlEvent::Cancel => self.cancel_all(),
+            UserControlEvent::Pause => {
+                self.paused = true;
+                (self.send_live(|ctrl| ctrl.pause().ticket()), false)
+            }
+            UserControlEvent::Resume => {
+                self.paused = false;
+                // the next member of a sequence that ended its previous one while paused
+                let waiting = self.mode == Mode::Sequence
+                    && self
+                        .members
+                        .iter()
+                        .position(|member| !member.started)
+                        .is_some_and(|index| {
+                            index > 0 && self.members[index - 1].outcome.is_some()
+                        });
+                if waiting {
+                    self.start_next();
+                }
+                (self.send_live(|ctrl| ctrl.resume().ticket()), waiting)
+            }
+            UserControlEvent::Signal(name) => {
+                (self.send_live(|ctrl| ctrl.signal(name).ticket()), false)
+            }
+            UserControlEvent::Invoke(input) => {
+                // the member blocked on it first, else the first one that runs,
+                // skipping members handed an input they have not picked up yet
+                let mut live: Vec<_> = self.live().map(|(_, member)| member).collect();
+                let blocked = |member: &&mut Member<O, U, CHAN_N, Q, ST, H, JOURNAL_N>| {
+                    member.ctrl.state().kind() == FlowState::Blocked
+                };
+                let index = live
+                    .iter()
+                    .position(|member| !member.fed && blocked(member))
+                    .or_else(|| live.iter().position(|member| !member.fed))
+                    .unwrap_or(0);
+                let Some(member) = live.get_mut(index) else {
+                    return Some(false);
+                };
+                member.fed = member.ctrl.invoke(input).is_ok();
+                return Some(member.fed);
+            }
+            UserControlEvent::Answer(id, input) => {
+                let waiting = self.live().map(|(_, member)| member).find(|member| {
+                    let mut pending = false;
+                    member
+                        .ctrl
+                        .for_each_query(|query, _| pending |= query == id);
+                    pending
+                });
+                return Some(waiting.is_some_and(|member| member.ctrl.answer(id, input).is_ok()));
+            }
+        };
+        if tickets.is_empty() || seq == 0 {
+            return Some(accepted || !tickets.is_empty());
+        }
+        self.relayed.push(Relayed {
+            seq,
+            tickets,
+            accepted,
+        });
+        None
+    }
+
+    /// used to pass a command on to every live member, the tickets of those it was sent to
+    fn send_live(
+        &mut self,
+        send: impl Fn(&UserController<U, CHAN_N, Q, ST, H, JOURNAL_N>) -> Option<Ticket>,
+    ) -> Vec<(usize, Ticket)> {
+        self.live()
+            .filter_map(|(index, member)| Some((index, send(&member.ctrl)?)))
+            .collect()
+    }
+
+    fn live(
+        &mut self,
+    ) -> impl Iterator<Item = (usize, &mut Member<O, U, CHAN_N, Q, ST, H, JOURNAL_N>)> {
+        self.members
+            .iter_mut()
+            .enumerate()
+            .filter(|(_, member)| member.is_live())
+    }
+
+    /// the tickets of the cancels sent, and whether a member was dropped right away
+    fn cancel_all(&mut self) -> (Vec<(usize, Ticket)>, bool) {
+        let mut tickets = Vec::new();
+        let mut dropped = false;
+        for (index, member) in self.members.iter_mut().enumerate() {
+            let running = member.outcome.is_none();
+            match member.cancel() {
+                Some(ticket) => tickets.push((index, ticket)),
+                None => dropped |= running,
+            }
+        }
+        (tickets, dropped)
+    }
+
+    /// used by a sequence to start the first member that has not started yet
+    fn start_next(&mut self) {
+        if let Some(next) = self.members.iter_mut().find(|member| !member.started) {
+            next.started = true;
+        }
+    }
+
+    /// used once the members were polled, acknowledges the commands every member consumed
+    fn settle_relayed(&mut self) {
+        let (ctrl, members, state) = (&self.ctrl, &self.members, &self.state);
+        self.relayed.retain_mut(|relayed| {
+            relayed
+                .tickets
+                .retain(|&(index, ticket)| match members[index].applied(ticket) {
+                    Some(applied) => {
+                        relayed.accepted |= applied;
+                        false
+                    }
+                    None => true,
+                });
+            if !relayed.tickets.is_empty() {
+                return true;
+            }
+            ctrl.acknowledge(relayed.seq, relayed.accepted, state);
+            false
+        });
+    }
+
+    /// used once the member at `index` ended
+    fn ended(&mut self, index: usize) {
+        let state = match &self.members[index].outcome {
+            Some(outcome) => outcome.state(),
+            None => return,
+        };
+        match self.mode {
+            // while paused the next member waits for the resume, it is not started to be paused
+            Mode::Sequence if state == FlowState::Completed => {
+                if !self.paused {
+                    self.start_next();
+                }
+            }
+            Mode::Select if state == FlowState::Cancelled => {}
+            Mode::JoinAll if state == FlowState::Completed => {}
+            _ => {
+                // the user does not wait on these cancels
+                self.cancel_all();
+            }
+        }
+    }
+
+    /// The state of the composite, derived from the states its members settled in
+    /// While the user paused it, it reports `Paused` whether or not each member could pause
+    fn derive(&self) -> FlowState {
+        let live: Vec<_> = self
+            .members
+            .iter()
+            .filter(|member| member.is_live())
+            .map(|member| member.ctrl.state().kind())
+            .collect();
+        let pending = self.members.iter().any(|member| member.outcome.is_none());
+        if self.paused && pending {
+            return FlowState::Paused;
+        }
+        if live.contains(&FlowState::Blocked) {
+            return FlowState::Blocked;
+        }
+        if !live.is_empty() && live.iter().all(|state| *state == FlowState::Paused) {
+            return FlowState::Paused;
+        }
+        if pending {
+            return FlowState::Running;
+        }
+        let ended = self
+            .members
+            .iter()
+            .filter_map(|member| member.outcome.as_ref().map(|outcome| outcome.state()));
+        let mut any_completed = false;
+        let mut all_completed = true;
+        for state in ended {
+            match state {
+                FlowState::Error => return FlowState::Error,
+                FlowState::Completed => any_completed = true,
+                _ => all_completed = false,
+            }
+        }
+        match self.mode {
+            Mode::Select if any_completed => FlowState::Completed,
+            _ if all_completed => FlowState::Completed,
+            _ => FlowState::Cancelled,
+        }
+    }
+}
+
+impl<
+    O: FlowOutput,
+    U: 'static,
+    const CHAN_N: usize,
+    Q: 'static,
+    ST: State,
+    H: 'static,
+    const JOURNAL_N: usize,
+> Future for Composite<O, U, CHAN_N, Q, ST, H, JOURNAL_N>
+where
+    H: Handler<ST, FlowEvent<U>, Output = Reply<U>>,
+{
+    type Output = Vec<OutcomeOf<O>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let ctrl = this.ctrl.clone();
+        let current = this.state.clone();
+        ctrl.relay(&current, cx.waker(), |seq, event| this.forward(seq, event));
+
+        // members started or cancelled along the way are polled in another round
+        loop {
+            let mut ended = false;
+            for index in 0..this.members.len() {
+                if this.members[index].poll_flow(cx) {
+                    this.ended(index);
+                    ended = true;
+                }
+            }
+            if !ended {
+                break;
+            }
+        }
+
+        this.state = this.derive();
+        this.settle_relayed();
+        ctrl.publish(&this.state);
+        if !this.state.is_terminal() {
+            return Poll::Pending;
+        }
+        let outcomes = this
+            .members
+            .iter_mut()
+            .filter_map(|member| member.outcome.take())
+            .collect();
+        Poll::Ready(outcomes)
+    }
+}
@@ -1,47 +1,163 @@
+#[cfg(feature = "alloc")]
+use super::family::Family;
+#[cfg(feature = "journal")]
+use super::journal::{Journal, JournalEntry};
+use super::lock::SpinLock;
+use super::trace;
 use super::{
-    AtomicWaker, FlowEvent, FlowEventHandler, FlowState, FnControlEvent, Handler, Reset,
-    UserControlEvent,
+    Acks, AtomicWaker, CommandError, FlowEvent, FlowEventHandler, FlowState, FnControlEvent,
+    Handler, JOURNAL_CAPACITY, Query, QueryBook, QueryId, Reply, Reset, Shared, State, StateCell,
+    StateChanges, UserControlEvent,
 };
+#[cfg(feature = "alloc")]
+use super::{ArcSlot, Flow, FlowHandle, FlowOutput, FnDataHandle, OutcomeOf};
 use crate::runtime::FlowRuntime;
-use anyhow::Result;
+#[cfg(feature = "alloc")]
+use alloc::{string::String, sync::Arc};
+use anyhow::{Result, bail};
 use core::future::Future;
 use core::pin::Pin;
 use core::task::{Context, Poll, Waker};
+use futures_core::FusedFuture;
+use heapless::Vec;
 use heapless::mpmc::MpMcQueue;
 
-pub struct BaseController<U: 'static, const CHAN_N: usize> {
-    channel: MpMcQueue<FlowEvent<U>, CHAN_N>,
-    handler: FlowEventHandler,
+pub struct BaseController<
+    U: 'static,
+    const CHAN_N: usize,
+    Q: 'static = (),
+    ST: State = FlowState,
+    H: 'static = FlowEventHandler,
+    const JOURNAL_N: usize = JOURNAL_CAPACITY,
+> {
+    /// events tagged with the sequence number of their acknowledgement, 0 if nobody waits on it
+    channel: MpMcQueue<(u32, FlowEvent<U>), CHAN_N>,
+    acks: Acks<ST, CHAN_N>,
+    /// queries the function is waiting on, answers are parked here until the function picks them up
+    queries: QueryBook<Q, U, CHAN_N>,
+    handler: H,
     waker: AtomicWaker,
+    /// the state the flow settled in, published for the user side
+    state: StateCell<ST>,
+    /// the last `JOURNAL_N` events the flow consumed
+    #[cfg(feature = "journal")]
+    journal: Journal<ST, JOURNAL_N>,
+    /// what the flow goes by in the path to a blocked flow, see `Flow::with_name`
+    name: SpinLock<&'static str>,
+    #[cfg(feature = "alloc")]
+    family: Family<BaseController<U, CHAN_N, Q, ST, H, JOURNAL_N>>,
 }
 
-impl<U: 'static, const CHAN_N: usize> Default for BaseController<U, CHAN_N> {
+impl<
+    U: 'static,
+    const CHAN_N: usize,
+    Q: 'static,
+    ST: State,
+    H: 'static + Default,
+    const JOURNAL_N: usize,
+> Default for BaseController<U, CHAN_N, Q, ST, H, JOURNAL_N>
+{
     fn default() -> Self {
+        Self::with_handler(H::default())
+    }
+}
+
+impl<U: 'static, const CHAN_N: usize, Q: 'static, ST: State, H: 'static, const JOURNAL_N: usize>
+    BaseController<U, CHAN_N, Q, ST, H, JOURNAL_N>
+{
+    /// A fresh controller, usable in a `static`
+    pub const fn with_handler(handler: H) -> Self {
         BaseController {
             channel: MpMcQueue::new(),
-            handler: FlowEventHandler::default(),
+            acks: Acks::new(),
+            queries: QueryBook::new(),
+            handler,
             waker: AtomicWaker::new(),
+            state: StateCell::new(),
+            #[cfg(feature = "journal")]
+            journal: Journal::new(),
+            name: SpinLock::new("flow"),
+            #[cfg(feature = "alloc")]
+            family: Family::new(),
         }
     }
 }
 
-impl<U: 'static, const CHAN_N: usize> Reset for BaseController<U, CHAN_N> {
+impl<U: 'static, const CHAN_N: usize, Q: 'static, ST: State, H: 'static, const JOURNAL_N: usize>
+    Reset for BaseController<U, CHAN_N, Q, ST, H, JOURNAL_N>
+{
     fn reset(&self) {
-        while let Some(_) = self.channel.dequeue() {}
-        // should find some way to invalidate the waker at this point, maybe.
+        while self.channel.dequeue().is_some() {}
+        self.acks.reset();
+        self.queries.reset();
+        self.state.reset();
+        #[cfg(feature = "journal")]
+        self.journal.reset();
+        *self.name.lock() = "flow";
+        #[cfg(feature = "alloc")]
+        self.family.reset();
+        // the flow that registered it is gone, events of the next one must not wake it
+        self.waker.take();
+        // commands of the old generation resolve as ended
+        self.acks.wake();
+    }
+}
+
+impl<U: 'static, const CHAN_N: usize, Q: 'static, ST: State, H: 'static, const JOURNAL_N: usize>
+    Drop for BaseController<U, CHAN_N, Q, ST, H, JOURNAL_N>
+{
+    fn drop(&mut self) {
+        // the queue leaves whatever is still in it undropped
+        while self.channel.dequeue().is_some() {}
+    }
+}
+
+impl<U: 'static, const CHAN_N: usize, Q: 'static, ST: State, H: 'static, const JOURNAL_N: usize>
+    BaseController<U, CHAN_N, Q, ST, H, JOURNAL_N>
+{
+    /// What the flow goes by in the path to a blocked flow
+    pub fn name(&self) -> &'static str {
+        *self.name.lock()
+    }
+
+    /// used by `Flow::with_name`
+    pub fn set_name(&self, name: &'static str) {
+        *self.name.lock() = name;
     }
 }
 
-impl<U: 'static, const CHAN_N: usize> BaseController<U, CHAN_N> {
+impl<U: 'static, const CHAN_N: usize, Q: 'static, ST: State, H: 'static, const JOURNAL_N: usize>
+    BaseController<U, CHAN_N, Q, ST, H, JOURNAL_N>
+where
+    H: Handler<ST, FlowEvent<U>, Output = Reply<U>>,
+{
     /// used by the user and function to send events and wake the flow future
     pub fn send(&self, item: FlowEvent<U>) -> Result<(), FlowEvent<U>> {
+        self.enqueue(0, item)
+    }
+
+    /// used by the user to send an event and wait for the flow to apply it
+    pub fn command(&self, item: FlowEvent<U>) -> Command<'_, U, CHAN_N, Q, ST, H, JOURNAL_N> {
+        let seq = self.acks.issue();
+        let ticket = match self.enqueue(seq, item) {
+            Ok(()) => Ok(seq),
+            Err(_) => Err(CommandError::Full),
+        };
+        Command {
+            inner: self,
+            generation: self.generation(),
+            ticket,
+        }
+    }
+
+    fn enqueue(&self, seq: u32, item: FlowEvent<U>) -> Result<(), FlowEvent<U>> {
         // maybe should check if waker exists before enqueue, or use a ready bit
-        match self.channel.enqueue(item) {
+        match self.channel.enqueue((seq, item)) {
             Ok(()) => {
                 self.waker.wake();
                 Ok(())
             }
-            Err(item) => Err(item),
+            Err((_, item)) => Err(item),
         }
     }
 
@@ -50,56 +166,289 @@ impl<U: 'static, const CHAN_N: usize> BaseController<U, CHAN_N> {
     /// updates the waker to be the one the future was polled with
     pub fn consume<F: Future>(
         &self,
-        current: &FlowState,
+        current: &ST,
         future: Pin<&mut F>,
         waker: &Waker,
-    ) -> (FlowState, Poll<F::Output>) {
+    ) -> (ST, Poll<F::Output>) {
         let mut state = current.clone();
 
-        while let Some(event) = self.channel.dequeue() {
-            state = self.handler.transition(&state, &event);
-            // do not know why Rust wants the government name here
-            <FlowEventHandler as Handler<FlowState, FlowEvent<U>>>::transient_exec(
-                &self.handler,
-                &state,
-            );
-
-            use std::println;
-            println!("Transitioning from {:?} to {:?}", current, state);
+        while let Some((seq, event)) = self.channel.dequeue() {
+            let previous = state;
+            state = self.handler.transition(&previous, &event);
+            if state != previous {
+                trace::transition!(previous, state, event.name());
+            }
+            #[cfg(feature = "journal")]
+            self.journal.record(event.name(), &previous, &state);
+            // a command that leaves the state untouched is not valid in that state
+            #[cfg_attr(not(feature = "alloc"), allow(unused_mut))]
+            let mut accepted = state != previous;
+            #[cfg(feature = "alloc")]
+            if accepted {
+                self.cascade(&event);
+            }
+            let released = self.handler.transient_exec(&previous, &state, event);
+            // input nobody here is waiting for goes to a blocked child flow, a stale answer is dropped
+            if let Some(reply) = released
+                && let Err(Reply::Next(_input)) = self.queries.deliver(reply)
+            {
+                #[cfg(feature = "alloc")]
+                {
+                    accepted |= self.pass_down(_input);
+                }
+            }
+            if seq != 0 {
+                self.acks.record(seq, accepted, &state);
+            }
         }
 
         self.waker.register(waker);
 
         let mut cx = Context::from_waker(waker);
-        let output = <FlowEventHandler as Handler<FlowState, FlowEvent<U>>>::exec(
-            &self.handler,
-            &state,
-            future,
-            &mut cx,
-        );
+        let output = self.handler.exec(&state, future, &mut cx);
         (state, output)
     }
+
+    /// used by composites, which run no function of their own but pass the events on to their children
+    /// `forward` gets the sequence number of each command and tells whether it reached a child, or
+    /// `None` if the composite acknowledges it with `acknowledge` once the children applied it.
+    /// The composite's own state is only derived from its children for reporting.
+    pub fn relay(
+        &self,
+        current: &ST,
+        waker: &Waker,
+        mut forward: impl FnMut(u32, FlowEvent<U>) -> Option<bool>,
+    ) {
+        while let Some((seq, event)) = self.channel.dequeue() {
+            #[cfg(feature = "journal")]
+            self.journal.record(event.name(), current, current);
+            if let Some(forwarded) = forward(seq, event)
+                && seq != 0
+            {
+                self.acks.record(seq, forwarded, current);
+            }
+        }
+
+        self.waker.register(waker);
+    }
+
+    /// used by composites for a command `relay` left to them, before they publish `state`
+    pub fn acknowledge(&self, seq: u32, accepted: bool, state: &ST) {
+        self.acks.record(seq, accepted, state);
+    }
+
+    /// The result of command `seq` sent in `generation`, if the flow consumed it or ended
+    fn command_result(&self, generation: u32, seq: u32) -> Option<Result<(), CommandError<ST>>> {
+        if self.generation() != generation {
+            // the slot was reset for another flow, ours ended and its results are gone
+            return Some(Err(CommandError::Ended));
+        }
+        if let Some(result) = self.acks.check(seq) {
+            return Some(result);
+        }
+        if self.state().is_terminal() {
+            // the last poll of the flow may have applied it right before ending
+            return Some(self.acks.check(seq).unwrap_or(Err(CommandError::Ended)));
+        }
+        None
+    }
+
+    /// used by the flow future to publish the state it settled in after a poll
+    pub fn publish(&self, state: &ST) {
+        #[cfg(feature = "alloc")]
+        let shown = self.settle(state);
+        #[cfg(feature = "alloc")]
+        let state = shown.as_ref().unwrap_or(state);
+        self.state.store(state);
+        if state.is_terminal() {
+            self.release();
+        }
+        // acknowledge consumed commands only once their outcome is observable through `state`,
+        // once the flow ended the commands still queued will never be applied
+        self.acks.wake();
+    }
+
+    /// Drop what only the ended flow could use, so it is not kept alive until the slot is reused
+    /// The state, journal and data pushed by the function stay readable until the next reset
+    fn release(&self) {
+        while self.channel.dequeue().is_some() {}
+        self.queries.reset();
+        self.waker.take();
+    }
+
+    /// The generation of the slot, bumped by every reset
+    /// handles of an older generation belong to a flow that has ended
+    pub fn generation(&self) -> u32 {
+        self.state.generation()
+    }
+
+    /// The last state published by the flow future
+    pub fn state(&self) -> ST {
+        self.state.load()
+    }
+
+    /// Stream of the state transitions published from now on
+    pub fn state_changes(&self) -> StateChanges<'_, ST> {
+        self.state.changes()
+    }
+
+    /// The events consumed by the flow, with the transitions they made
+    #[cfg(feature = "journal")]
+    pub fn journal(&self) -> &Journal<ST, JOURNAL_N> {
+        &self.journal
+    }
+
+    /// used by the function while it waits on user input
+    /// the function is only polled while the flow is running, so every pending poll has to park
+    /// the flow again, e.g. after a pause raced ahead of the first block event
+    fn park(&self, cx: &mut Context<'_>) {
+        if self.send(FlowEvent::Fn(FnControlEvent::Block)).is_err() {
+            // the channel is drained on every flow poll, try again next time around
+            cx.waker().wake_by_ref();
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<U: 'static, const CHAN_N: usize, Q: 'static, ST: State, H: 'static, const JOURNAL_N: usize>
+    BaseController<U, CHAN_N, Q, ST, H, JOURNAL_N>
+where
+    H: Handler<ST, FlowEvent<U>, Output = Reply<U>>,
+{
+    /// used while consuming, passes pause, resume and cancel on to the child flows
+    fn cascade(&self, event: &FlowEvent<U>) {
+        let forward: fn() -> UserControlEvent<U> = match event {
+            FlowEvent::User(UserControlEvent::Pause) => || UserControlEvent::Pause,
+            FlowEvent::User(UserControlEvent::Resume) => || UserControlEvent::Resume,
+            FlowEvent::User(UserControlEvent::Cancel) => || UserControlEvent::Cancel,
+            _ => return,
+        };
+        for child in self.family.children.lock().iter() {
+            // a child with a full channel misses it
+            let _ = child.send(FlowEvent::User(forward()));
+        }
+    }
+
+    /// used while consuming, hands invoked input nobody here waits on to the first blocked child flow
+    /// returns whether a child got it
+    fn pass_down(&self, input: U) -> bool {
+        let children = self.family.children.lock();
+        let Some(child) = children.iter().find(|child| child.family.is_blocked()) else {
+            return false;
+        };
+        child
+            .send(FlowEvent::User(UserControlEvent::Invoke(input)))
+            .is_ok()
+    }
+
+    /// used when publishing, keeps track of the child flows and whether any of them is blocked
+    /// a flow that runs while one of its children is blocked shows as blocked, if its states can
+    fn settle(&self, state: &ST) -> Option<ST> {
+        let mut children = self.family.children.lock();
+        if state.is_terminal() {
+            // child flows do not outlive their parent
+            for child in children.drain(..) {
+                let _ = child.send(FlowEvent::User(UserControlEvent::Cancel));
+            }
+        } else {
+            children.retain(|child| !child.state().is_terminal());
+        }
+        let child_blocked = children.iter().any(|child| child.family.is_blocked());
+        drop(children);
+
+        let blocked_self = state.kind() == FlowState::Blocked;
+        if self
+            .family
+            .set_blocked(blocked_self, blocked_self || child_blocked)
+            && let Some(parent) = self.family.parent()
+        {
+            // the parent settles again and shows whether anything below it is blocked
+            parent.waker.wake();
+        }
+        if child_blocked && state.kind() == FlowState::Running {
+            let blocked = ST::from_kind(FlowState::Blocked);
+            if blocked.kind() == FlowState::Blocked {
+                return Some(blocked);
+            }
+        }
+        None
+    }
+
+    /// used by `UserController::blocked_path`, appends the path to the flow that waits on the user
+    fn blocked_path(&self, path: &mut String) -> bool {
+        if !self.family.is_blocked() {
+            return false;
+        }
+        if !path.is_empty() {
+            path.push('/');
+        }
+        path.push_str(self.name());
+        if !self.family.is_blocked_self() {
+            let children = self.family.children.lock();
+            children.iter().any(|child| child.blocked_path(path));
+        }
+        true
+    }
 }
 
 /// Controller for the async function being controlled
-/// Can only send Block events and use runtime methods
-pub struct FnController<R: FlowRuntime, U: 'static, const CHAN_N: usize> {
-    inner: &'static BaseController<U, CHAN_N>,
-    runtime: &'static R,
+/// Can only send Block and Signal events and use runtime methods
+pub struct FnController<
+    R: FlowRuntime,
+    U: 'static,
+    const CHAN_N: usize,
+    Q: 'static = (),
+    ST: State = FlowState,
+    H: 'static = FlowEventHandler,
+    const JOURNAL_N: usize = JOURNAL_CAPACITY,
+> {
+    inner: Shared<BaseController<U, CHAN_N, Q, ST, H, JOURNAL_N>>,
+    runtime: R,
 }
 
-impl<R: FlowRuntime, U: 'static, const CHAN_N: usize> FnController<R, U, CHAN_N> {
-    pub fn new(inner: &'static BaseController<U, CHAN_N>, runtime: &'static R) -> Self {
+impl<
+    R: FlowRuntime,
+    U: 'static,
+    const CHAN_N: usize,
+    Q: 'static,
+    ST: State,
+    H: 'static,
+    const JOURNAL_N: usize,
+> FnController<R, U, CHAN_N, Q, ST, H, JOURNAL_N>
+where
+    H: Handler<ST, FlowEvent<U>, Output = Reply<U>>,
+{
+    pub fn new(inner: Shared<BaseController<U, CHAN_N, Q, ST, H, JOURNAL_N>>, runtime: R) -> Self {
+        #[cfg(feature = "journal")]
+        inner.journal.set_clock(runtime.clone());
         Self { inner, runtime }
     }
 
-    /// Send a block event to indicate the function needs to wait for user input
-    /// TODO this should be an async function that resolves to a U when the user resolves the block
-    pub fn block(&self) -> Result<()> {
-        self.inner.send(FlowEvent::Fn(FnControlEvent::Block));
-        todo!(
-            "This should be an async function that resolves to a U when the user resolves the block"
-        );
+    /// Block the flow until the user resolves it with `UserController::invoke`
+    /// The flow sits in `FlowState::Blocked` meanwhile and this resolves to the invoked input
+    pub fn block(&self) -> UserQueryFuture<'_, U, CHAN_N, Q, ST, H, JOURNAL_N> {
+        UserQueryFuture::new(&self.inner, None)
+    }
+
+    /// Ask the user a question and wait for the answer
+    /// Several asks may be outstanding at once, each is answered by id with `UserController::answer`
+    /// The flow sits in `FlowState::Blocked` while an awaited ask is unanswered, the whole function
+    /// waits meanwhile, work it joined with the ask included
+    pub fn ask(&self, prompt: Q) -> UserQueryFuture<'_, U, CHAN_N, Q, ST, H, JOURNAL_N> {
+        UserQueryFuture::new(&self.inner, Some(prompt))
+    }
+
+    /// Send a named signal to the flow's handler, e.g. to enter a custom state
+    /// The built-in `FlowEventHandler` ignores signals
+    pub fn signal(&self, name: &'static str) -> Result<()> {
+        if self
+            .inner
+            .send(FlowEvent::Fn(FnControlEvent::Signal(name)))
+            .is_err()
+        {
+            bail!("control channel is full");
+        }
+        Ok(())
     }
 
     /// Yield control to allow other tasks to run
@@ -111,84 +460,411 @@ impl<R: FlowRuntime, U: 'static, const CHAN_N: usize> FnController<R, U, CHAN_N>
     pub fn delay_ms(&self, millis: u32) -> impl Future<Output = ()> + '_ {
         self.runtime.delay_ms(millis)
     }
+
+    /// Spawn `function` with `init` as a child flow on the runtime of this one
+    /// Pausing, resuming or cancelling this flow does the same to the child, which is cancelled once
+    /// this flow ends. While the child is blocked this flow shows as blocked too, see
+    /// `UserController::blocked_path`, and input invoked here that this flow does not wait on goes to it.
+    /// Queries the child asks are listed and answered through the returned handle, not this flow's.
+    #[cfg(feature = "alloc")]
+    #[allow(clippy::type_complexity)]
+    pub async fn spawn_child<Fun, I, F, UD, FD, const DATA_N: usize>(
+        &self,
+        function: Fun,
+        init: I,
+    ) -> Result<
+        FlowHandle<OutcomeOf<F::Output>, U, UD, FD, CHAN_N, DATA_N, Q, ST, H, R::Handle, JOURNAL_N>,
+        R::Error,
+    >
+    where
+        Fun: FnOnce(
+            I,
+            FnController<R, U, CHAN_N, Q, ST, H, JOURNAL_N>,
+            FnDataHandle<UD, FD, DATA_N>,
+        ) -> F,
+        F: Future,
+        F::Output: FlowOutput,
+        OutcomeOf<F::Output>: Send + 'static,
+        Flow<F, U, CHAN_N, Q, ST, H, JOURNAL_N>: Send + 'static,
+        UD: 'static,
+        FD: 'static,
+        H: Default,
+    {
+        let slot = ArcSlot::new();
+        let child = slot.base();
+        // linked before it runs, so that no change of the child goes unnoticed
+        child.family.set_parent(&self.inner);
+        self.inner.family.children.lock().push(child.clone());
+        let spawned = self.runtime.spawn_flow(function, init, slot);
+        if spawned.is_err() {
+            let mut children = self.inner.family.children.lock();
+            children.retain(|linked| !Arc::ptr_eq(linked, &child));
+        }
+        spawned
+    }
 }
 
 /// Controller for user operations
-/// Can send Pause/Resume/Cancel/Invoke events
-pub struct UserController<U: 'static, const CHAN_N: usize> {
-    inner: &'static BaseController<U, CHAN_N>,
+/// Can send Pause/Resume/Cancel/Invoke/Answer/Signal events
+pub struct UserController<
+    U: 'static,
+    const CHAN_N: usize,
+    Q: 'static = (),
+    ST: State = FlowState,
+    H: 'static = FlowEventHandler,
+    const JOURNAL_N: usize = JOURNAL_CAPACITY,
+> {
+    inner: Shared<BaseController<U, CHAN_N, Q, ST, H, JOURNAL_N>>,
+    /// the generation of the slot this controller was handed out for
+    generation: u32,
 }
 
-impl<U: 'static, const CHAN_N: usize> UserController<U, CHAN_N> {
-    pub fn new(inner: &'static BaseController<U, CHAN_N>) -> Self {
-        Self { inner }
+impl<U: 'static, const CHAN_N: usize, Q: 'static, ST: State, H: 'static, const JOURNAL_N: usize>
+    UserController<U, CHAN_N, Q, ST, H, JOURNAL_N>
+where
+    H: Handler<ST, FlowEvent<U>, Output = Reply<U>>,
+{
+    pub fn new(inner: Shared<BaseController<U, CHAN_N, Q, ST, H, JOURNAL_N>>) -> Self {
+        let generation = inner.generation();
+        Self { inner, generation }
     }
 
-    /// Pause the flow execution
-    pub fn pause(&self) -> Result<()> {
-        self.inner.send(FlowEvent::User(UserControlEvent::Pause));
+    /// Whether the slot still belongs to the flow this controller was handed out for
+    /// Once it has been reset for another flow every command is rejected
+    pub fn is_current(&self) -> bool {
+        self.inner.generation() == self.generation
+    }
+
+    fn command(&self, event: UserControlEvent<U>) -> Command<'_, U, CHAN_N, Q, ST, H, JOURNAL_N> {
+        if !self.is_current() {
+            return Command {
+                inner: &self.inner,
+                generation: self.generation,
+                ticket: Err(CommandError::Ended),
+            };
+        }
+        self.inner.command(FlowEvent::User(event))
+    }
+
+    fn send(&self, event: UserControlEvent<U>) -> Result<()> {
+        if !self.is_current() {
+            bail!("flow has ended");
+        }
+        if self.inner.send(FlowEvent::User(event)).is_err() {
+            bail!("control channel is full");
+        }
         Ok(())
     }
 
+    /// Pause the flow execution
+    /// The command is sent right away, await it to know when the flow has actually paused
+    pub fn pause(&self) -> Command<'_, U, CHAN_N, Q, ST, H, JOURNAL_N> {
+        self.command(UserControlEvent::Pause)
+    }
+
     /// Resume the flow execution
-    pub fn resume(&self) -> Result<()> {
-        self.inner.send(FlowEvent::User(UserControlEvent::Resume));
-        Ok(())
+    /// The command is sent right away, await it to know when the flow is running again
+    pub fn resume(&self) -> Command<'_, U, CHAN_N, Q, ST, H, JOURNAL_N> {
+        self.command(UserControlEvent::Resume)
     }
 
     /// Cancel the flow execution
-    pub fn cancel(&self) -> Result<()> {
-        self.inner.send(FlowEvent::User(UserControlEvent::Cancel));
-        Ok(())
+    /// The command is sent right away, await it to know when the flow has been cancelled
+    pub fn cancel(&self) -> Command<'_, U, CHAN_N, Q, ST, H, JOURNAL_N> {
+        self.command(UserControlEvent::Cancel)
+    }
+
+    /// Send a named signal to the flow's handler, e.g. to enter a custom state
+    /// The built-in `FlowEventHandler` rejects signals
+    pub fn signal(&self, name: &'static str) -> Command<'_, U, CHAN_N, Q, ST, H, JOURNAL_N> {
+        self.command(UserControlEvent::Signal(name))
+    }
+
+    /// used by composites, the result of a command the way awaiting it would resolve, if it did
+    #[cfg(feature = "alloc")]
+    pub(crate) fn command_result(&self, ticket: Ticket) -> Option<Result<(), CommandError<ST>>> {
+        self.inner.command_result(ticket.generation, ticket.seq)
     }
 
     /// Send user input to unblock the function
+    /// The input answers the oldest query the function is waiting on
     pub fn invoke(&self, input: U) -> Result<()> {
-        self.inner
-            .send(FlowEvent::User(UserControlEvent::Invoke(input)));
-        Ok(())
+        self.send(UserControlEvent::Invoke(input))
+    }
+
+    /// Snapshot of the flow state, as of the flow's last poll
+    pub fn state(&self) -> ST {
+        self.inner.state.load_at(self.generation)
+    }
+
+    /// Stream of the flow's state transitions, ending once the flow has ended
+    pub fn state_changes(&self) -> StateChanges<'_, ST> {
+        self.inner.state.changes_at(self.generation)
+    }
+
+    /// Answer a specific query the function asked
+    /// Queries of child flows are answered through the child's own handle
+    pub fn answer(&self, id: QueryId, input: U) -> Result<()> {
+        if self.is_current() && !self.inner.queries.is_pending(id) {
+            bail!("query {} is not pending", id.0);
+        }
+        self.send(UserControlEvent::Answer(id, input))
+    }
+
+    /// Snapshot of the queries waiting for an answer, oldest first
+    pub fn queries(&self) -> Vec<Query<Q>, CHAN_N>
+    where
+        Q: Clone,
+    {
+        let mut queries = Vec::new();
+        self.for_each_query(|id, prompt| {
+            let _ = queries.push(Query {
+                id,
+                prompt: prompt.cloned(),
+            });
+        });
+        queries
+    }
+
+    /// The last events the flow consumed with the transitions they made and when, oldest first
+    /// Rejected events are in there too, with `from` and `to` the same
+    #[cfg(feature = "journal")]
+    pub fn journal(&self) -> Vec<JournalEntry<ST>, JOURNAL_N> {
+        if !self.is_current() {
+            return Vec::new();
+        }
+        self.inner.journal.entries()
+    }
+
+    /// Where the flow waits on the user, e.g. `parent/child` when one of its child flows is blocked
+    /// `None` while neither the flow nor any flow below it is blocked
+    #[cfg(feature = "alloc")]
+    pub fn blocked_path(&self) -> Option<String> {
+        if !self.is_current() {
+            return None;
+        }
+        let mut path = String::new();
+        self.inner.blocked_path(&mut path).then_some(path)
+    }
+
+    /// Visit the queries waiting for an answer without cloning their prompts, oldest first
+    /// The query book is locked while `f` runs, so it must not call back into this controller
+    pub fn for_each_query(&self, f: impl FnMut(QueryId, Option<&Q>)) {
+        if self.is_current() {
+            self.inner.queries.for_each_pending(f);
+        }
     }
 }
 
 /// Controller for user the flow future itself
 /// Can only read events
-pub struct FlowFutureController<U: 'static, const CHAN_N: usize> {
-    inner: &'static BaseController<U, CHAN_N>,
+pub struct FlowFutureController<
+    U: 'static,
+    const CHAN_N: usize,
+    Q: 'static = (),
+    ST: State = FlowState,
+    H: 'static = FlowEventHandler,
+    const JOURNAL_N: usize = JOURNAL_CAPACITY,
+> {
+    inner: Shared<BaseController<U, CHAN_N, Q, ST, H, JOURNAL_N>>,
 }
 
-impl<U: 'static, const CHAN_N: usize> FlowFutureController<U, CHAN_N> {
-    pub fn new(inner: &'static BaseController<U, CHAN_N>) -> Self {
+impl<U: 'static, const CHAN_N: usize, Q: 'static, ST: State, H: 'static, const JOURNAL_N: usize>
+    FlowFutureController<U, CHAN_N, Q, ST, H, JOURNAL_N>
+where
+    H: Handler<ST, FlowEvent<U>, Output = Reply<U>>,
+{
+    pub fn new(inner: Shared<BaseController<U, CHAN_N, Q, ST, H, JOURNAL_N>>) -> Self {
         Self { inner }
     }
 
     pub fn consume<F: Future>(
         &self,
-        current: &FlowState,
+        current: &ST,
         future: Pin<&mut F>,
         waker: &Waker,
-    ) -> (FlowState, Poll<F::Output>) {
+    ) -> (ST, Poll<F::Output>) {
         self.inner.consume(current, future, waker)
     }
+
+    pub fn publish(&self, state: &ST) {
+        self.inner.publish(state)
+    }
+}
+
+impl<U: 'static, const CHAN_N: usize, Q: 'static, ST: State, H: 'static, const JOURNAL_N: usize>
+    FlowFutureController<U, CHAN_N, Q, ST, H, JOURNAL_N>
+{
+    pub fn set_name(&self, name: &'static str) {
+        self.inner.set_name(name)
+    }
 }
 
-// pub struct UserQueryFuture<T> {
-//     query_id: u64,
-//     response: Option<T>,
-// }
-
-// impl<T> Future for UserQueryFuture<T>
-// where
-//     T: Unpin,
-// {
-//     type Output = T;
-
-//     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-//         let this = self.get_mut();
-//         if let Some(response) = this.response.take() {
-//             Poll::Ready(response)
-//         } else {
-//             cx.waker().wake_by_ref();
-//             Poll::Pending
-//         }
-//     }
-// }
+/// Acknowledgement of a control command
+/// Resolves once the flow consumed the command, with the reason if it did not take effect.
+pub struct Command<
+    'a,
+    U: 'static,
+    const CHAN_N: usize,
+    Q: 'static = (),
+    ST: State = FlowState,
+    H: 'static = FlowEventHandler,
+    const JOURNAL_N: usize = JOURNAL_CAPACITY,
+> {
+    inner: &'a BaseController<U, CHAN_N, Q, ST, H, JOURNAL_N>,
+    generation: u32,
+    ticket: Result<u32, CommandError<ST>>,
+}
+
+impl<U: 'static, const CHAN_N: usize, Q: 'static, ST: State, H: 'static, const JOURNAL_N: usize>
+    Command<'_, U, CHAN_N, Q, ST, H, JOURNAL_N>
+where
+    H: Handler<ST, FlowEvent<U>, Output = Reply<U>>,
+{
+    /// Whether the command made it into the control channel, it resolves as rejected right away otherwise
+    pub fn is_sent(&self) -> bool {
+        self.ticket.is_ok()
+    }
+
+    /// used by composites to keep track of the command without borrowing the controller,
+    /// `None` if it was not sent
+    #[cfg(feature = "alloc")]
+    pub(crate) fn ticket(&self) -> Option<Ticket> {
+        let seq = *self.ticket.as_ref().ok()?;
+        Some(Ticket {
+            generation: self.generation,
+            seq,
+        })
+    }
+
+    fn check(&self) -> Option<Result<(), CommandError<ST>>> {
+        match &self.ticket {
+            Ok(seq) => self.inner.command_result(self.generation, *seq),
+            Err(e) => Some(Err(e.clone())),
+        }
+    }
+}
+
+impl<U: 'static, const CHAN_N: usize, Q: 'static, ST: State, H: 'static, const JOURNAL_N: usize>
+    Future for Command<'_, U, CHAN_N, Q, ST, H, JOURNAL_N>
+where
+    H: Handler<ST, FlowEvent<U>, Output = Reply<U>>,
+{
+    type Output = Result<(), CommandError<ST>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if let Some(result) = self.check() {
+            return Poll::Ready(result);
+        }
+        if let Ok(seq) = self.ticket {
+            self.inner.acks.register(seq, cx.waker());
+        }
+        match self.check() {
+            Some(result) => Poll::Ready(result),
+            None => Poll::Pending,
+        }
+    }
+}
+
+/// A sent command, checked with `UserController::command_result`
+#[cfg(feature = "alloc")]
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Ticket {
+    generation: u32,
+    seq: u32,
+}
+
+/// Future for one question the function asked the user
+/// The query is opened on first poll and withdrawn if the future is dropped before it is answered
+/// It is fused, once it resolved it stays pending instead of asking again
+pub struct UserQueryFuture<
+    'a,
+    U: 'static,
+    const CHAN_N: usize,
+    Q: 'static = (),
+    ST: State = FlowState,
+    H: 'static = FlowEventHandler,
+    const JOURNAL_N: usize = JOURNAL_CAPACITY,
+> {
+    inner: &'a BaseController<U, CHAN_N, Q, ST, H, JOURNAL_N>,
+    prompt: Option<Q>,
+    id: Option<QueryId>,
+    answered: bool,
+}
+
+impl<'a, U: 'static, const CHAN_N: usize, Q: 'static, ST: State, H, const JOURNAL_N: usize>
+    UserQueryFuture<'a, U, CHAN_N, Q, ST, H, JOURNAL_N>
+{
+    fn new(inner: &'a BaseController<U, CHAN_N, Q, ST, H, JOURNAL_N>, prompt: Option<Q>) -> Self {
+        Self {
+            inner,
+            prompt,
+            id: None,
+            answered: false,
+        }
+    }
+
+    /// The id the user answers this query with, once it has been polled
+    pub fn id(&self) -> Option<QueryId> {
+        self.id
+    }
+}
+
+// the prompt is only ever moved out, never pinned
+impl<U: 'static, const CHAN_N: usize, Q: 'static, ST: State, H: 'static, const JOURNAL_N: usize>
+    Unpin for UserQueryFuture<'_, U, CHAN_N, Q, ST, H, JOURNAL_N>
+{
+}
+
+impl<U: 'static, const CHAN_N: usize, Q: 'static, ST: State, H: 'static, const JOURNAL_N: usize>
+    Future for UserQueryFuture<'_, U, CHAN_N, Q, ST, H, JOURNAL_N>
+where
+    H: Handler<ST, FlowEvent<U>, Output = Reply<U>>,
+{
+    type Output = U;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        if this.answered {
+            return Poll::Pending;
+        }
+
+        match this.id {
+            Some(id) => {
+                if let Some(input) = this.inner.queries.take(id) {
+                    this.id = None;
+                    this.answered = true;
+                    return Poll::Ready(input);
+                }
+            }
+            // a full book is retried once one of the outstanding queries is answered
+            None => match this.inner.queries.open(this.prompt.take()) {
+                Ok(id) => this.id = Some(id),
+                Err(prompt) => this.prompt = prompt,
+            },
+        }
+
+        this.inner.park(cx);
+        Poll::Pending
+    }
+}
+
+impl<U: 'static, const CHAN_N: usize, Q: 'static, ST: State, H: 'static, const JOURNAL_N: usize>
+    FusedFuture for UserQueryFuture<'_, U, CHAN_N, Q, ST, H, JOURNAL_N>
+where
+    H: Handler<ST, FlowEvent<U>, Output = Reply<U>>,
+{
+    fn is_terminated(&self) -> bool {
+        self.answered
+    }
+}
+
+impl<U: 'static, const CHAN_N: usize, Q: 'static, ST: State, H: 'static, const JOURNAL_N: usize>
+    Drop for UserQueryFuture<'_, U, CHAN_N, Q, ST, H, JOURNAL_N>
+{
+    fn drop(&mut self) {
+        if let Some(id) = self.id {
+            self.inner.queries.close(id);
+        }
+    }
+}
@@ -1,61 +1,531 @@
-use super::Reset;
+use super::{AtomicWaker, Reset, Shared, trace};
+use core::fmt;
+#[cfg(feature = "futures")]
+use core::pin::Pin;
+use core::task::{Context, Poll};
+#[cfg(feature = "futures")]
+use futures_core::Stream;
+#[cfg(feature = "futures")]
+use futures_sink::Sink;
 use heapless::mpmc::MpMcQueue;
+use portable_atomic::{AtomicBool, AtomicU8, AtomicU32, Ordering};
+
+/// Why data could not be sent
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataError {
+    /// the queue is full and its overflow policy is `Overflow::Error`
+    Full,
+    /// the other end of the channel is gone, or the channel was reset for another flow
+    Closed,
+}
+
+impl fmt::Display for DataError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DataError::Full => write!(f, "data channel is full"),
+            DataError::Closed => write!(f, "data channel is closed"),
+        }
+    }
+}
+
+impl core::error::Error for DataError {}
+
+/// What pushing to a full queue does, set per direction of a channel
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Overflow {
+    /// wait for space, for data that must never be lost
+    /// `try_push` hands the data back
+    #[default]
+    Block,
+    /// make room by dropping the oldest queued item, for samples where the latest wins
+    DropOldest,
+    /// drop the data being pushed
+    DropNewest,
+    /// hand the data back to the caller right away
+    Error,
+}
+
+impl Overflow {
+    fn from_bits(bits: u8) -> Self {
+        match bits {
+            1 => Overflow::DropOldest,
+            2 => Overflow::DropNewest,
+            3 => Overflow::Error,
+            _ => Overflow::Block,
+        }
+    }
+
+    fn to_bits(self) -> u8 {
+        match self {
+            Overflow::Block => 0,
+            Overflow::DropOldest => 1,
+            Overflow::DropNewest => 2,
+            Overflow::Error => 3,
+        }
+    }
+}
+
+/// How many items the overflow policies dropped since the last reset, per direction
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Dropped {
+    /// from the user to the function
+    pub user_data: u32,
+    /// from the function to the user
+    pub fn_data: u32,
+}
+
+/// what became of a push that did not wait
+enum Push<T> {
+    Done,
+    Full(T),
+    Closed(T),
+}
+
+/// One direction of a data channel
+/// Only one task at a time is woken for items and one for space, like the control channel.
+struct Pipe<T, const N: usize> {
+    /// the direction, for logs
+    name: &'static str,
+    queue: MpMcQueue<T, N>,
+    /// whoever waits for an item
+    readable: AtomicWaker,
+    /// whoever waits for space
+    writable: AtomicWaker,
+    /// set once either end is gone, pushes fail and receivers get what is left
+    closed: AtomicBool,
+    overflow: AtomicU8,
+    dropped: AtomicU32,
+}
+
+impl<T, const N: usize> Pipe<T, N> {
+    const fn new(name: &'static str) -> Self {
+        Pipe {
+            name,
+            queue: MpMcQueue::new(),
+            readable: AtomicWaker::new(),
+            writable: AtomicWaker::new(),
+            closed: AtomicBool::new(false),
+            overflow: AtomicU8::new(0),
+            dropped: AtomicU32::new(0),
+        }
+    }
+}
+
+impl<T, const N: usize> Pipe<T, N> {
+    fn overflow(&self) -> Overflow {
+        Overflow::from_bits(self.overflow.load(Ordering::Relaxed))
+    }
+
+    fn set_overflow(&self, overflow: Overflow) {
+        self.overflow.store(overflow.to_bits(), Ordering::Relaxed);
+        // pushes blocked under the old policy may go ahead now
+        self.writable.wake();
+    }
+
+    fn try_push(&self, item: T) -> Push<T> {
+        if self.closed.load(Ordering::Acquire) {
+            trace::push!(self.name, "closed");
+            return Push::Closed(item);
+        }
+        let mut item = match self.queue.enqueue(item) {
+            Ok(()) => {
+                trace::push!(self.name, "queued");
+                self.readable.wake();
+                return Push::Done;
+            }
+            Err(item) => item,
+        };
+        match self.overflow() {
+            Overflow::Block | Overflow::Error => {
+                trace::push!(self.name, "full");
+                Push::Full(item)
+            }
+            Overflow::DropNewest => {
+                trace::push!(self.name, "dropped newest");
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                Push::Done
+            }
+            Overflow::DropOldest => loop {
+                // the receiver may take the oldest item first, then nothing needs dropping
+                if self.queue.dequeue().is_some() {
+                    trace::push!(self.name, "dropped oldest");
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                }
+                match self.queue.enqueue(item) {
+                    Ok(()) => {
+                        self.readable.wake();
+                        return Push::Done;
+                    }
+                    Err(back) => item = back,
+                }
+            },
+        }
+    }
+
+    fn try_recv(&self) -> Option<T> {
+        let item = self.queue.dequeue()?;
+        self.writable.wake();
+        Some(item)
+    }
+
+    /// pushes the item as the overflow policy says, leaves it in place if it fails
+    fn poll_push(&self, item: &mut Option<T>, cx: &mut Context<'_>) -> Poll<Result<(), DataError>> {
+        for registered in [false, true] {
+            let Some(value) = item.take() else {
+                return Poll::Ready(Ok(()));
+            };
+            match self.try_push(value) {
+                Push::Done => return Poll::Ready(Ok(())),
+                Push::Closed(value) => {
+                    *item = Some(value);
+                    return Poll::Ready(Err(DataError::Closed));
+                }
+                Push::Full(value) => {
+                    *item = Some(value);
+                    if self.overflow() == Overflow::Error {
+                        return Poll::Ready(Err(DataError::Full));
+                    }
+                }
+            }
+            // the receiver may have made space between the check and the registration
+            if !registered {
+                self.writable.register(cx.waker());
+            }
+        }
+        Poll::Pending
+    }
+
+    /// an item, `None` once the pipe is closed and drained
+    fn poll_recv(&self, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        if let Some(item) = self.ready() {
+            return Poll::Ready(item);
+        }
+        self.readable.register(cx.waker());
+        // the sender may have pushed between the check and the registration
+        match self.ready() {
+            Some(item) => Poll::Ready(item),
+            None => Poll::Pending,
+        }
+    }
+
+    fn ready(&self) -> Option<Option<T>> {
+        match self.try_recv() {
+            Some(item) => Some(Some(item)),
+            // pushes fail once closed, so nothing can follow
+            None if self.closed.load(Ordering::Acquire) => Some(self.try_recv()),
+            None => None,
+        }
+    }
+
+    fn close(&self) {
+        self.closed.store(true, Ordering::Release);
+        self.wake_all();
+    }
+
+    /// lets whoever waits on the pipe notice it was closed or reset
+    fn wake_all(&self) {
+        self.readable.wake();
+        self.writable.wake();
+    }
+
+    // the queue leaves whatever is still in it undropped
+    fn drain(&self) {
+        while self.queue.dequeue().is_some() {}
+    }
+}
 
 pub struct DataChannel<UD: 'static, FD: 'static, const N: usize> {
-    pub user_data: MpMcQueue<UD, N>,
-    pub fn_data: MpMcQueue<FD, N>,
+    /// from the user to the function
+    user_data: Pipe<UD, N>,
+    /// from the function to the user
+    fn_data: Pipe<FD, N>,
+    /// bumped by every reset, handles of an older generation are cut off
+    generation: AtomicU32,
 }
 
 impl<UD: 'static, FD: 'static, const N: usize> Default for DataChannel<UD, FD, N> {
     fn default() -> Self {
-        DataChannel {
-            user_data: MpMcQueue::new(),
-            fn_data: MpMcQueue::new(),
-        }
+        Self::new()
     }
 }
 
 impl<UD: 'static, FD: 'static, const N: usize> Reset for DataChannel<UD, FD, N> {
     fn reset(&self) {
-        while let Some(_) = self.user_data.dequeue() {}
-        while let Some(_) = self.fn_data.dequeue() {}
+        self.generation.fetch_add(1, Ordering::AcqRel);
+        self.drain();
+        // the overflow policies are configuration and stay as they are
+        for closed in [&self.user_data.closed, &self.fn_data.closed] {
+            closed.store(false, Ordering::Release);
+        }
+        for dropped in [&self.user_data.dropped, &self.fn_data.dropped] {
+            dropped.store(0, Ordering::Relaxed);
+        }
+        self.user_data.wake_all();
+        self.fn_data.wake_all();
+    }
+}
+
+impl<UD: 'static, FD: 'static, const N: usize> Drop for DataChannel<UD, FD, N> {
+    fn drop(&mut self) {
+        self.drain();
     }
 }
 
+impl<UD: 'static, FD: 'static, const N: usize> DataChannel<UD, FD, N> {
+    pub const fn new() -> Self {
+        DataChannel {
+            user_data: Pipe::new("user_data"),
+            fn_data: Pipe::new("fn_data"),
+            generation: AtomicU32::new(0),
+        }
+    }
+
+    /// The generation of the channel, bumped by every reset
+    pub fn generation(&self) -> u32 {
+        self.generation.load(Ordering::Acquire)
+    }
+
+    /// Set what pushing to a full queue does, for each direction
+    pub fn set_overflow(&self, user_data: Overflow, fn_data: Overflow) {
+        self.user_data.set_overflow(user_data);
+        self.fn_data.set_overflow(fn_data);
+    }
+
+    /// How many items the overflow policies dropped since the last reset
+    pub fn dropped(&self) -> Dropped {
+        Dropped {
+            user_data: self.user_data.dropped.load(Ordering::Relaxed),
+            fn_data: self.fn_data.dropped.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Close both directions, used when either handle drops
+    /// Pushes fail from then on, receivers get what is still queued and then `None`
+    pub fn close(&self) {
+        self.user_data.close();
+        self.fn_data.close();
+    }
+
+    fn drain(&self) {
+        self.user_data.drain();
+        self.fn_data.drain();
+    }
+}
+
+/// The function's end of a data channel, dropping it closes the channel
+/// It is dropped along with the function once the flow ends, which ends the user's stream.
 pub struct FnDataHandle<UD: 'static, FD: 'static, const N: usize> {
-    producer: &'static MpMcQueue<FD, N>,
-    consumer: &'static MpMcQueue<UD, N>,
+    channel: Shared<DataChannel<UD, FD, N>>,
+    generation: u32,
+    /// the item a `Sink` started to send, pushed once there is space
+    #[cfg(feature = "futures")]
+    pending: Option<FD>,
 }
 
 impl<UD: 'static, FD: 'static, const N: usize> FnDataHandle<UD, FD, N> {
-    pub fn new(producer: &'static MpMcQueue<FD, N>, consumer: &'static MpMcQueue<UD, N>) -> Self {
-        Self { producer, consumer }
+    pub fn new(channel: Shared<DataChannel<UD, FD, N>>) -> Self {
+        let generation = channel.generation();
+        Self {
+            channel,
+            generation,
+            #[cfg(feature = "futures")]
+            pending: None,
+        }
+    }
+
+    /// Whether the channel still belongs to the flow this handle was handed out for
+    pub fn is_current(&self) -> bool {
+        self.channel.generation() == self.generation
+    }
+
+    /// How many items the overflow policies dropped so far
+    pub fn dropped(&self) -> Dropped {
+        self.channel.dropped()
+    }
+
+    /// Send data to the user, a full queue is handled by the channel's overflow policy
+    /// Resolves to `Err` with the data if it could not be sent
+    pub async fn push(&self, data: FD) -> Result<(), FD> {
+        let mut data = Some(data);
+        core::future::poll_fn(|cx| {
+            if !self.is_current() {
+                return Poll::Ready(());
+            }
+            self.channel.fn_data.poll_push(&mut data, cx).map(|_| ())
+        })
+        .await;
+        data.map_or(Ok(()), Err)
+    }
+
+    /// Send data to the user without waiting, `Err` with the data if it could not be sent
+    pub fn try_push(&self, data: FD) -> Result<(), FD> {
+        if !self.is_current() {
+            return Err(data);
+        }
+        match self.channel.fn_data.try_push(data) {
+            Push::Done => Ok(()),
+            Push::Full(data) | Push::Closed(data) => Err(data),
+        }
     }
 
-    pub fn push(&self, data: FD) {
-        self.producer.enqueue(data);
+    /// Wait for data from the user, `None` once the channel is closed and drained
+    pub async fn recv(&self) -> Option<UD> {
+        core::future::poll_fn(|cx| {
+            if !self.is_current() {
+                return Poll::Ready(None);
+            }
+            self.channel.user_data.poll_recv(cx)
+        })
+        .await
     }
 
-    pub fn recv(&self) -> Option<UD> {
-        self.consumer.dequeue()
+    /// Data from the user, if any has arrived
+    pub fn try_recv(&self) -> Option<UD> {
+        if !self.is_current() {
+            return None;
+        }
+        self.channel.user_data.try_recv()
     }
 }
 
+/// The user's end of a data channel, dropping it closes the channel
 pub struct UserDataHandle<UD: 'static, FD: 'static, const N: usize> {
-    producer: &'static MpMcQueue<UD, N>,
-    consumer: &'static MpMcQueue<FD, N>,
+    channel: Shared<DataChannel<UD, FD, N>>,
+    generation: u32,
 }
 
 impl<UD: 'static, FD: 'static, const N: usize> UserDataHandle<UD, FD, N> {
-    pub fn new(producer: &'static MpMcQueue<UD, N>, consumer: &'static MpMcQueue<FD, N>) -> Self {
-        Self { producer, consumer }
+    pub fn new(channel: Shared<DataChannel<UD, FD, N>>) -> Self {
+        let generation = channel.generation();
+        Self {
+            channel,
+            generation,
+        }
+    }
+
+    /// Whether the channel still belongs to the flow this handle was handed out for
+    pub fn is_current(&self) -> bool {
+        self.channel.generation() == self.generation
+    }
+
+    /// How many items the overflow policies dropped so far
+    pub fn dropped(&self) -> Dropped {
+        self.channel.dropped()
     }
 
-    pub fn push(&self, data: UD) {
-        self.producer.enqueue(data);
+    /// Send data to the function, a full queue is handled by the channel's overflow policy
+    /// Resolves to `Err` with the data if it could not be sent
+    pub async fn push(&self, data: UD) -> Result<(), UD> {
+        let mut data = Some(data);
+        core::future::poll_fn(|cx| {
+            if !self.is_current() {
+                return Poll::Ready(());
+            }
+            self.channel.user_data.poll_push(&mut data, cx).map(|_| ())
+        })
+        .await;
+        data.map_or(Ok(()), Err)
     }
 
-    pub fn recv(&self) -> Option<FD> {
-        self.consumer.dequeue()
+    /// Send data to the function without waiting, `Err` with the data if it could not be sent
+    pub fn try_push(&self, data: UD) -> Result<(), UD> {
+        if !self.is_current() {
+            return Err(data);
+        }
+        match self.channel.user_data.try_push(data) {
+            Push::Done => Ok(()),
+            Push::Full(data) | Push::Closed(data) => Err(data),
+        }
+    }
+
+    /// Wait for data from the function, `None` once the channel is closed and drained
+    pub async fn recv(&self) -> Option<FD> {
+        core::future::poll_fn(|cx| {
+            if !self.is_current() {
+                return Poll::Ready(None);
+            }
+            self.channel.fn_data.poll_recv(cx)
+        })
+        .await
+    }
+
+    /// Data from the function, if any has arrived
+    pub fn try_recv(&self) -> Option<FD> {
+        if !self.is_current() {
+            return None;
+        }
+        self.channel.fn_data.try_recv()
+    }
+}
+
+impl<UD: 'static, FD: 'static, const N: usize> Drop for FnDataHandle<UD, FD, N> {
+    fn drop(&mut self) {
+        if self.is_current() {
+            self.channel.close();
+            // nobody is left to read it, what the function pushed stays for the user
+            self.channel.user_data.drain();
+        }
+    }
+}
+
+impl<UD: 'static, FD: 'static, const N: usize> Drop for UserDataHandle<UD, FD, N> {
+    fn drop(&mut self) {
+        if self.is_current() {
+            self.channel.close();
+        }
+    }
+}
+
+// the pending item is only ever moved out, never pinned
+#[cfg(feature = "futures")]
+impl<UD: 'static, FD: 'static, const N: usize> Unpin for FnDataHandle<UD, FD, N> {}
+
+#[cfg(feature = "futures")]
+impl<UD: 'static, FD: 'static, const N: usize> Sink<FD> for FnDataHandle<UD, FD, N> {
+    type Error = DataError;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), DataError>> {
+        self.poll_flush(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: FD) -> Result<(), DataError> {
+        let this = self.get_mut();
+        if !this.is_current() {
+            return Err(DataError::Closed);
+        }
+        this.pending = Some(item);
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), DataError>> {
+        let this = self.get_mut();
+        if !this.is_current() {
+            return Poll::Ready(Err(DataError::Closed));
+        }
+        let result = core::task::ready!(this.channel.fn_data.poll_push(&mut this.pending, cx));
+        // the item is not retried on the next flush
+        this.pending = None;
+        Poll::Ready(result)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), DataError>> {
+        let result = core::task::ready!(self.as_mut().poll_flush(cx));
+        if self.is_current() {
+            self.channel.close();
+        }
+        Poll::Ready(result)
+    }
+}
+
+/// Ends once the function's end is gone, usually because the flow ended
+#[cfg(feature = "futures")]
+impl<UD: 'static, FD: 'static, const N: usize> Stream for UserDataHandle<UD, FD, N> {
+    type Item = FD;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<FD>> {
+        if !self.is_current() {
+            return Poll::Ready(None);
+        }
+        self.channel.fn_data.poll_recv(cx)
     }
 }
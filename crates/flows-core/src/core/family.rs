@@ -0,0 +1,81 @@
+use super::lock::SpinLock;
+use super::{Reset, Shared};
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use portable_atomic::{AtomicBool, Ordering};
+
+/// The child flows a flow spawned with `FnController::spawn_child`, and its own parent
+pub struct Family<C: 'static> {
+    pub(crate) children: SpinLock<Vec<Arc<C>>>,
+    /// notified whenever the flow starts or stops waiting on the user
+    parent: SpinLock<Option<Parent<C>>>,
+    /// the flow or one of the flows below it waits on the user
+    blocked: AtomicBool,
+    /// the flow itself waits on the user
+    blocked_self: AtomicBool,
+}
+
+/// Link to the parent, not keeping a heap allocated one alive
+enum Parent<C: 'static> {
+    Static(&'static C),
+    Arc(Weak<C>),
+}
+
+impl<C> Default for Family<C> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<C> Family<C> {
+    pub const fn new() -> Self {
+        Family {
+            children: SpinLock::new(Vec::new()),
+            parent: SpinLock::new(None),
+            blocked: AtomicBool::new(false),
+            blocked_self: AtomicBool::new(false),
+        }
+    }
+}
+
+impl<C> Reset for Family<C> {
+    fn reset(&self) {
+        self.children.lock().clear();
+        self.parent.lock().take();
+        self.blocked.store(false, Ordering::Relaxed);
+        self.blocked_self.store(false, Ordering::Relaxed);
+    }
+}
+
+impl<C> Family<C> {
+    /// used by the parent before it spawns the flow
+    pub fn set_parent(&self, parent: &Shared<C>) {
+        let parent = match parent {
+            Shared::Static(parent) => Parent::Static(*parent),
+            Shared::Arc(parent) => Parent::Arc(Arc::downgrade(parent)),
+        };
+        *self.parent.lock() = Some(parent);
+    }
+
+    /// The parent, while it is still around
+    pub fn parent(&self) -> Option<Shared<C>> {
+        match self.parent.lock().as_ref()? {
+            Parent::Static(parent) => Some(Shared::Static(parent)),
+            Parent::Arc(parent) => parent.upgrade().map(Shared::Arc),
+        }
+    }
+
+    /// used when publishing, returns whether the flow started or stopped waiting on the user
+    pub fn set_blocked(&self, blocked_self: bool, blocked: bool) -> bool {
+        self.blocked_self.store(blocked_self, Ordering::Release);
+        self.blocked.swap(blocked, Ordering::AcqRel) != blocked
+    }
+
+    pub fn is_blocked(&self) -> bool {
+        self.blocked.load(Ordering::Acquire)
+    }
+
+    pub fn is_blocked_self(&self) -> bool {
+        self.blocked_self.load(Ordering::Acquire)
+    }
+}
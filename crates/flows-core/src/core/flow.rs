@@ -1,37 +1,204 @@
-use crate::core::{FlowFutureController, FlowState};
+use crate::core::trace;
+use crate::core::{
+    FlowEvent, FlowEventHandler, FlowFutureController, FlowState, Handler, JOURNAL_CAPACITY, Reply,
+    State,
+};
+use core::convert::Infallible;
 use core::future::Future;
 use core::pin::Pin;
 use core::task::{Context, Poll};
 
+/// How a flow ended
+#[derive(Debug, Clone, PartialEq)]
+pub enum FlowOutcome<T, E = Infallible> {
+    /// the function ran to completion
+    Completed(T),
+    /// the user cancelled the flow before the function finished
+    Cancelled,
+    /// the function returned an error
+    Failed(E),
+}
+
+impl<T, E> FlowOutcome<T, E> {
+    /// The kind of state the flow ends up in for this outcome
+    pub fn state(&self) -> FlowState {
+        match self {
+            FlowOutcome::Completed(_) => FlowState::Completed,
+            FlowOutcome::Cancelled => FlowState::Cancelled,
+            FlowOutcome::Failed(_) => FlowState::Error,
+        }
+    }
+}
+
+/// How a flow running a function with output `T` ends
+pub type OutcomeOf<T> = FlowOutcome<<T as FlowOutput>::Value, <T as FlowOutput>::Error>;
+
+/// Output of a function run as a flow
+/// An `Err` ends the flow in `FlowState::Error`, anything else in `FlowState::Completed`
+/// Implemented for `()` and `Result`, a function returning any other value wraps it in `Done`
+#[diagnostic::on_unimplemented(
+    message = "`{Self}` is not the output of a flow",
+    note = "a flow function returns `()`, a `Result`, or any other value wrapped in `flows::Done`"
+)]
+pub trait FlowOutput {
+    type Value;
+    type Error;
+
+    fn into_result(self) -> Result<Self::Value, Self::Error>;
+}
+
+impl FlowOutput for () {
+    type Value = ();
+    type Error = Infallible;
+
+    fn into_result(self) -> Result<(), Infallible> {
+        Ok(())
+    }
+}
+
+impl<T, E> FlowOutput for Result<T, E> {
+    type Value = T;
+    type Error = E;
+
+    fn into_result(self) -> Result<T, E> {
+        self
+    }
+}
+
+/// Output of a function that cannot fail, e.g. `async fn count(..) -> Done<u32>`
+/// The flow completes with the value inside
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Done<T>(pub T);
+
+impl<T> FlowOutput for Done<T> {
+    type Value = T;
+    type Error = Infallible;
+
+    fn into_result(self) -> Result<T, Infallible> {
+        Ok(self.0)
+    }
+}
+
 /// A controllable future that can be paused, resumed, and cancelled
-pub struct Flow<F: Future, U: 'static, const CHAN_N: usize> {
-    inner: F,
-    ctrl: FlowFutureController<U, CHAN_N>,
-    state: FlowState,
+pub struct Flow<
+    F: Future,
+    U: 'static,
+    const CHAN_N: usize,
+    Q: 'static = (),
+    ST: State = FlowState,
+    H: 'static = FlowEventHandler,
+    const JOURNAL_N: usize = JOURNAL_CAPACITY,
+> {
+    /// dropped as soon as the flow reaches a terminal state, releasing whatever the function holds
+    inner: Option<F>,
+    ctrl: FlowFutureController<U, CHAN_N, Q, ST, H, JOURNAL_N>,
+    state: ST,
+    /// everything logged while the flow is polled, the function included, goes in here
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
 }
 
-impl<F: Future, U, const CHAN_N: usize> Flow<F, U, CHAN_N> {
+/// used by launchers to name flows after their function, e.g. `job` for `my_crate::jobs::job`
+/// a closure goes by the function it is defined in
+pub(crate) fn name_of<Fun>() -> &'static str {
+    let name = core::any::type_name::<Fun>();
+    name.rsplit("::")
+        .find(|segment| !segment.starts_with('{'))
+        .unwrap_or(name)
+}
+
+/// used by the supervisor and composites, which keep their flows on the heap
+#[cfg(feature = "alloc")]
+pub(crate) type BoxedFlow<F, U, const CHAN_N: usize, Q, ST, H, const JOURNAL_N: usize> =
+    Pin<alloc::boxed::Box<Flow<F, U, CHAN_N, Q, ST, H, JOURNAL_N>>>;
+
+/// numbers the flows in their spans
+#[cfg(feature = "tracing")]
+static NEXT_FLOW_ID: portable_atomic::AtomicU32 = portable_atomic::AtomicU32::new(0);
+
+impl<F: Future, U, const CHAN_N: usize, Q, ST: State, H, const JOURNAL_N: usize>
+    Flow<F, U, CHAN_N, Q, ST, H, JOURNAL_N>
+{
     /// Create a new Flow wrapping the given future
-    pub fn new(future: F, ctrl: FlowFutureController<U, CHAN_N>) -> Self {
+    pub fn new(future: F, ctrl: FlowFutureController<U, CHAN_N, Q, ST, H, JOURNAL_N>) -> Self {
         Self {
-            inner: future,
+            inner: Some(future),
             ctrl,
-            state: FlowState::default(),
+            state: ST::default(),
+            #[cfg(feature = "tracing")]
+            span: tracing::debug_span!(
+                "flow",
+                id = NEXT_FLOW_ID.fetch_add(1, portable_atomic::Ordering::Relaxed)
+            ),
         }
     }
+
+    /// Run the flow in `span` instead of the numbered one it gets by default
+    #[cfg(feature = "tracing")]
+    pub fn with_span(mut self, span: tracing::Span) -> Self {
+        self.span = span;
+        self
+    }
+
+    /// Name the flow, for the path to a blocked flow, see `UserController::blocked_path`
+    pub fn with_name(self, name: &'static str) -> Self {
+        self.ctrl.set_name(name);
+        self
+    }
+
+    /// The state the flow was left in by its last poll
+    pub fn state(&self) -> &ST {
+        &self.state
+    }
 }
 
-impl<F: Future, U, const CHAN_N: usize> Future for Flow<F, U, CHAN_N> {
-    type Output = F::Output;
+impl<F, U, const CHAN_N: usize, Q, ST: State, H, const JOURNAL_N: usize> Future
+    for Flow<F, U, CHAN_N, Q, ST, H, JOURNAL_N>
+where
+    F: Future,
+    F::Output: FlowOutput,
+    H: Handler<ST, FlowEvent<U>, Output = Reply<U>>,
+{
+    type Output = FlowOutcome<<F::Output as FlowOutput>::Value, <F::Output as FlowOutput>::Error>;
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         let waker = cx.waker().clone();
 
         let this = unsafe { self.get_unchecked_mut() };
-        let inner_future = unsafe { Pin::new_unchecked(&mut this.inner) };
+        #[cfg(feature = "tracing")]
+        let _span = this.span.clone().entered();
+        // the inner future is never moved out of the flow, only dropped in place
+        let mut inner = unsafe { Pin::new_unchecked(&mut this.inner) };
+        let inner_future = inner
+            .as_mut()
+            .as_pin_mut()
+            .expect("`Flow` polled after completion");
         let current = this.state.clone();
         let (next, output) = this.ctrl.consume(&current, inner_future, &waker);
-        this.state = next;
-        output
+        trace::poll!(next, output.is_ready());
+
+        let outcome = match output {
+            Poll::Ready(output) => match output.into_result() {
+                Ok(value) => FlowOutcome::Completed(value),
+                Err(error) => FlowOutcome::Failed(error),
+            },
+            Poll::Pending if next.kind() == FlowState::Cancelled => FlowOutcome::Cancelled,
+            Poll::Pending => {
+                this.ctrl.publish(&next);
+                this.state = next;
+                return Poll::Pending;
+            }
+        };
+
+        // keep the state the handler moved to if it already is of the right kind
+        this.state = if next.kind() == outcome.state() {
+            next
+        } else {
+            ST::from_kind(outcome.state())
+        };
+        // the function and whatever it holds are gone by the time the end is observable
+        inner.set(None);
+        this.ctrl.publish(&this.state);
+        Poll::Ready(outcome)
     }
 }
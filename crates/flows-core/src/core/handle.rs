@@ -0,0 +1,102 @@
+use super::{
+    Command, FlowEvent, FlowEventHandler, FlowState, Handler, JOURNAL_CAPACITY, Join, JoinCell,
+    Reply, State, StateChanges, UserController, UserDataHandle,
+};
+use alloc::sync::Arc;
+
+/// A flow spawned with `FlowRuntime::spawn_flow`
+pub struct FlowHandle<
+    T: 'static,
+    U: 'static,
+    UD: 'static,
+    FD: 'static,
+    const CHAN_N: usize,
+    const DATA_N: usize,
+    Q: 'static = (),
+    ST: State = FlowState,
+    H: 'static = FlowEventHandler,
+    S = (),
+    const JOURNAL_N: usize = JOURNAL_CAPACITY,
+> {
+    ctrl: UserController<U, CHAN_N, Q, ST, H, JOURNAL_N>,
+    data: UserDataHandle<UD, FD, DATA_N>,
+    join: Arc<JoinCell<T>>,
+    task: S,
+}
+
+impl<
+    T: 'static,
+    U: 'static,
+    UD: 'static,
+    FD: 'static,
+    const CHAN_N: usize,
+    const DATA_N: usize,
+    Q: 'static,
+    ST: State,
+    H: 'static,
+    S,
+    const JOURNAL_N: usize,
+> FlowHandle<T, U, UD, FD, CHAN_N, DATA_N, Q, ST, H, S, JOURNAL_N>
+where
+    H: Handler<ST, FlowEvent<U>, Output = Reply<U>>,
+{
+    /// used by `FlowRuntime::spawn_flow` once the flow is spawned
+    pub fn new(
+        ctrl: UserController<U, CHAN_N, Q, ST, H, JOURNAL_N>,
+        data: UserDataHandle<UD, FD, DATA_N>,
+        join: Arc<JoinCell<T>>,
+        task: S,
+    ) -> Self {
+        Self {
+            ctrl,
+            data,
+            join,
+            task,
+        }
+    }
+
+    /// Pause the flow, await the command to know when it took effect
+    pub fn pause(&self) -> Command<'_, U, CHAN_N, Q, ST, H, JOURNAL_N> {
+        self.ctrl.pause()
+    }
+
+    /// Resume the flow, await the command to know when it took effect
+    pub fn resume(&self) -> Command<'_, U, CHAN_N, Q, ST, H, JOURNAL_N> {
+        self.ctrl.resume()
+    }
+
+    /// Cancel the flow, await the command to know when it took effect
+    pub fn cancel(&self) -> Command<'_, U, CHAN_N, Q, ST, H, JOURNAL_N> {
+        self.ctrl.cancel()
+    }
+
+    /// Snapshot of the flow state, as of the flow's last poll
+    pub fn state(&self) -> ST {
+        self.ctrl.state()
+    }
+
+    /// Stream of the flow's state transitions, ending once the flow has ended
+    pub fn state_changes(&self) -> StateChanges<'_, ST> {
+        self.ctrl.state_changes()
+    }
+
+    /// The user controller, for input, queries and signals
+    pub fn ctrl(&self) -> &UserController<U, CHAN_N, Q, ST, H, JOURNAL_N> {
+        &self.ctrl
+    }
+
+    /// The user's end of the data channel
+    pub fn data(&mut self) -> &mut UserDataHandle<UD, FD, DATA_N> {
+        &mut self.data
+    }
+
+    /// What the runtime's spawner handed back for the flow's task
+    pub fn task(&self) -> &S {
+        &self.task
+    }
+
+    /// Resolves to the `FlowOutcome` once the flow ends, only one join gets it, see `Join`
+    pub fn join(&self) -> Join<'_, T> {
+        self.join.join()
+    }
+}
@@ -1,25 +1,35 @@
+use super::{QueryId, Reply};
 use core::pin::Pin;
 use core::task::{Context, Poll};
+
+/// The state machine behind a flow
+/// `BaseController` and `Flow` take any handler over a `State`, `FlowEventHandler` is the default
 pub trait Handler<ST, E>: Default {
+    /// Data handed back to the function when a transition releases it, e.g. the user's input for a block
+    type Output;
+
     fn transition(&self, current: &ST, event: &E) -> ST;
-    fn transient_exec(&self, state: &ST) -> ();
-    fn exec<F: Future>(
-        &self,
-        state: &FlowState,
-        future: Pin<&mut F>,
-        cx: &mut Context,
-    ) -> Poll<F::Output>;
+    fn transient_exec(&self, previous: &ST, state: &ST, event: E) -> Option<Self::Output>;
+    fn exec<F: Future>(&self, state: &ST, future: Pin<&mut F>, cx: &mut Context)
+    -> Poll<F::Output>;
 }
 
 pub enum UserControlEvent<U> {
     Pause,
     Resume,
+    /// answers the oldest query the function is waiting on
     Invoke(U),
+    /// answers a specific query the function is waiting on
+    Answer(QueryId, U),
     Cancel,
+    /// named event for custom handlers
+    Signal(&'static str),
 }
 
 pub enum FnControlEvent {
     Block,
+    /// named event for custom handlers
+    Signal(&'static str),
 }
 
 pub enum FlowEvent<U> {
@@ -27,8 +37,25 @@ pub enum FlowEvent<U> {
     Fn(FnControlEvent),
 }
 
-#[derive(Debug, Clone, PartialEq)]
+impl<U> FlowEvent<U> {
+    /// Short name of the event for logs, signals go by their own name
+    pub fn name(&self) -> &'static str {
+        match self {
+            FlowEvent::User(UserControlEvent::Pause) => "pause",
+            FlowEvent::User(UserControlEvent::Resume) => "resume",
+            FlowEvent::User(UserControlEvent::Invoke(_)) => "invoke",
+            FlowEvent::User(UserControlEvent::Answer(..)) => "answer",
+            FlowEvent::User(UserControlEvent::Cancel) => "cancel",
+            FlowEvent::Fn(FnControlEvent::Block) => "block",
+            FlowEvent::User(UserControlEvent::Signal(name))
+            | FlowEvent::Fn(FnControlEvent::Signal(name)) => name,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
 pub enum FlowState {
+    #[default]
     Running,
     Paused,
     Blocked,
@@ -37,21 +64,12 @@ pub enum FlowState {
     Error,
 }
 
-impl Default for FlowState {
-    fn default() -> Self {
-        FlowState::Running
-    }
-}
-
+#[derive(Debug, Clone, Copy, Default)]
 pub struct FlowEventHandler {}
 
-impl Default for FlowEventHandler {
-    fn default() -> Self {
-        Self {}
-    }
-}
-
 impl<U> Handler<FlowState, FlowEvent<U>> for FlowEventHandler {
+    type Output = Reply<U>;
+
     fn transition(&self, current: &FlowState, event: &FlowEvent<U>) -> FlowState {
         match (current, event) {
             // terminal states (maybe we should error instead)
@@ -73,18 +91,30 @@ impl<U> Handler<FlowState, FlowEvent<U>> for FlowEventHandler {
             (FlowState::Blocked, FlowEvent::User(UserControlEvent::Invoke(_))) => {
                 FlowState::Running
             }
+            (FlowState::Blocked, FlowEvent::User(UserControlEvent::Answer(..))) => {
+                FlowState::Running
+            }
 
             // no change - clone the current state
             _ => current.clone(),
         }
     }
 
-    fn transient_exec(&self, state: &FlowState) -> () {
+    fn transient_exec(
+        &self,
+        _previous: &FlowState,
+        state: &FlowState,
+        event: FlowEvent<U>,
+    ) -> Option<Reply<U>> {
         // Execute behavior for a state we are passing through while transitioning through events
-        // right now this is a noop, but eventually we may want to add some logic here for blocking/invoking
-        // so that the invocation data is not lost
-        match state {
-            _ => {}
+        // answers are released whenever the function is still alive to receive them, e.g. while
+        // paused, or running again after one of several outstanding queries was answered.
+        // The function is not polled while blocked, a query still unanswered blocks it again
+        match (state, event) {
+            (FlowState::Cancelled | FlowState::Completed | FlowState::Error, _) => None,
+            (_, FlowEvent::User(UserControlEvent::Invoke(input))) => Some(Reply::Next(input)),
+            (_, FlowEvent::User(UserControlEvent::Answer(id, input))) => Some(Reply::To(id, input)),
+            _ => None,
         }
     }
 
@@ -0,0 +1,104 @@
+use super::lock::SpinLock;
+use super::{FlowState, JOURNAL_CAPACITY, Reset, State};
+use crate::runtime::Clock;
+#[cfg(feature = "alloc")]
+use alloc::boxed::Box;
+use heapless::{Deque, Vec};
+
+/// One event consumed by a flow
+#[derive(Debug, Clone, PartialEq)]
+pub struct JournalEntry<ST = FlowState> {
+    /// when the event was consumed, in microseconds on the runtime's clock
+    pub at_us: u64,
+    /// `FlowEvent::name` of the event
+    pub event: &'static str,
+    pub from: ST,
+    /// the same as `from` if the handler did not accept the event
+    pub to: ST,
+}
+
+/// Where the timestamps of the entries come from
+enum Stamp {
+    /// entries are stamped 0 until the function's controller installs its runtime
+    Unset,
+    /// a clock read without its runtime, see `Clock::now_fn`
+    Fn(fn() -> u64),
+    #[cfg(feature = "alloc")]
+    Runtime(Box<dyn Clock + Send + Sync>),
+}
+
+struct Entries<ST, const N: usize> {
+    stamp: Stamp,
+    entries: Deque<JournalEntry<ST>, N>,
+}
+
+/// Bounded record of the last `N` events a flow consumed and the transitions they made
+pub struct Journal<ST: State = FlowState, const N: usize = JOURNAL_CAPACITY> {
+    inner: SpinLock<Entries<ST, N>>,
+}
+
+impl<ST: State, const N: usize> Default for Journal<ST, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<ST: State, const N: usize> Journal<ST, N> {
+    pub const fn new() -> Self {
+        Journal {
+            inner: SpinLock::new(Entries {
+                stamp: Stamp::Unset,
+                entries: Deque::new(),
+            }),
+        }
+    }
+}
+
+impl<ST: State, const N: usize> Reset for Journal<ST, N> {
+    fn reset(&self) {
+        let mut inner = self.inner.lock();
+        inner.entries.clear();
+        // the next flow's controller installs its runtime
+        inner.stamp = Stamp::Unset;
+    }
+}
+
+impl<ST: State, const N: usize> Journal<ST, N> {
+    /// used by the function's controller to stamp entries with the runtime's clock
+    /// A clock that cannot be read as a plain function needs `alloc`, entries are stamped 0 without it
+    pub fn set_clock<C: Clock + Send + Sync + 'static>(&self, clock: C) {
+        let stamp = match clock.now_fn() {
+            Some(now_us) => Stamp::Fn(now_us),
+            #[cfg(feature = "alloc")]
+            None => Stamp::Runtime(Box::new(clock)),
+            #[cfg(not(feature = "alloc"))]
+            None => Stamp::Unset,
+        };
+        self.inner.lock().stamp = stamp;
+    }
+
+    /// used by the flow future for every event it consumes
+    pub fn record(&self, event: &'static str, from: &ST, to: &ST) {
+        let mut inner = self.inner.lock();
+        let at_us = match &inner.stamp {
+            Stamp::Unset => 0,
+            Stamp::Fn(now_us) => now_us(),
+            #[cfg(feature = "alloc")]
+            Stamp::Runtime(clock) => clock.now_us(),
+        };
+        if inner.entries.is_full() {
+            inner.entries.pop_front();
+        }
+        let _ = inner.entries.push_back(JournalEntry {
+            at_us,
+            event,
+            from: from.clone(),
+            to: to.clone(),
+        });
+    }
+
+    /// Copy of the entries, oldest first
+    pub fn entries(&self) -> Vec<JournalEntry<ST>, N> {
+        self.inner.lock().entries.iter().cloned().collect()
+    }
+}
@@ -0,0 +1,267 @@
+use super::lock::SpinLock;
+use super::waker::WakerSet;
+use super::{FlowEventHandler, FlowState, JOURNAL_CAPACITY, State, UserController, UserDataHandle};
+use core::fmt;
+use core::future::Future;
+use core::ops::Deref;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use portable_atomic::{AtomicU8, AtomicU32, Ordering};
+
+const IDLE: u8 = 0;
+const RUNNING: u8 = 1;
+const DONE: u8 = 2;
+
+/// Why a flow could not be launched
+#[derive(Debug, Clone, PartialEq)]
+pub enum LaunchError<E> {
+    /// the previous run of the flow has not reached a terminal state yet
+    Busy,
+    /// the runtime refused to spawn the flow
+    Spawn(E),
+}
+
+impl<E: fmt::Debug> fmt::Display for LaunchError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LaunchError::Busy => write!(f, "flow is already running"),
+            LaunchError::Spawn(e) => write!(f, "could not spawn the flow: {:?}", e),
+        }
+    }
+}
+
+impl<E: fmt::Debug> core::error::Error for LaunchError<E> {}
+
+/// Why a join did not get the outcome of its run
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum JoinError {
+    /// the flow was launched again, the outcome of the run this join belongs to is gone
+    Replaced,
+    /// another join of the same run took the outcome
+    Taken,
+    /// the flow panicked, its task was dropped without an outcome
+    Panicked,
+    /// the task of the flow was dropped before the flow ended, e.g. its runtime shut down
+    Dropped,
+}
+
+impl fmt::Display for JoinError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JoinError::Replaced => write!(f, "the flow was launched again"),
+            JoinError::Taken => write!(f, "another join took the outcome"),
+            JoinError::Panicked => write!(f, "the flow panicked"),
+            JoinError::Dropped => write!(f, "the flow was dropped before it ended"),
+        }
+    }
+}
+
+impl core::error::Error for JoinError {}
+
+/// Hands the outcome of a spawned flow over to whoever joins it
+/// One run at a time: it has to be claimed before the flow is spawned
+pub struct JoinCell<T> {
+    state: AtomicU8,
+    /// bumped by every claim, a join of an earlier run resolves as replaced
+    run: AtomicU32,
+    value: SpinLock<Option<Result<T, JoinError>>>,
+    wakers: WakerSet,
+}
+
+impl<T> Default for JoinCell<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> JoinCell<T> {
+    pub const fn new() -> Self {
+        Self {
+            state: AtomicU8::new(IDLE),
+            run: AtomicU32::new(0),
+            value: SpinLock::new(None),
+            wakers: WakerSet::new(),
+        }
+    }
+
+    /// Start a new run, fails while the previous one has not delivered its outcome
+    pub fn claim(&self) -> bool {
+        let claimed = [IDLE, DONE].into_iter().any(|from| {
+            self.state
+                .compare_exchange(from, RUNNING, Ordering::AcqRel, Ordering::Acquire)
+                .is_ok()
+        });
+        if claimed {
+            let mut value = self.value.lock();
+            self.run.fetch_add(1, Ordering::AcqRel);
+            // an outcome nobody joined
+            value.take();
+            drop(value);
+            // joins of the previous run get to see it was replaced
+            self.wakers.wake();
+        }
+        claimed
+    }
+
+    /// used by the launcher when the flow could not be spawned after all
+    pub fn release(&self) {
+        self.state.store(IDLE, Ordering::Release);
+    }
+
+    /// used by the spawned task once the flow resolved
+    pub fn set(&self, value: T) {
+        self.finish(Ok(value));
+    }
+
+    /// used by `Deliver` when the task is dropped before the flow resolved
+    fn abandon(&self, error: JoinError) {
+        self.finish(Err(error));
+    }
+
+    fn finish(&self, value: Result<T, JoinError>) {
+        *self.value.lock() = Some(value);
+        self.state.store(DONE, Ordering::Release);
+        self.wakers.wake();
+    }
+
+    /// A future resolving to the outcome of the current run
+    pub fn join(&self) -> Join<'_, T> {
+        Join {
+            cell: self,
+            run: self.run.load(Ordering::Acquire),
+            id: self.wakers.id(),
+        }
+    }
+}
+
+/// Resolves to the outcome of a launched flow
+/// Only one join gets the outcome, the others resolve as `JoinError::Taken`,
+/// and a join of a run that was launched again after it ended as `JoinError::Replaced`
+pub struct Join<'a, T> {
+    cell: &'a JoinCell<T>,
+    run: u32,
+    /// what the join registers its waker under
+    id: u32,
+}
+
+impl<T> Future for Join<'_, T> {
+    type Output = Result<T, JoinError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let cell = self.cell;
+        cell.wakers.register(self.id, cx.waker());
+        let mut value = cell.value.lock();
+        if cell.run.load(Ordering::Acquire) != self.run {
+            return Poll::Ready(Err(JoinError::Replaced));
+        }
+        if let Some(value) = value.take() {
+            return Poll::Ready(value);
+        }
+        if cell.state.load(Ordering::Acquire) == DONE {
+            return Poll::Ready(Err(JoinError::Taken));
+        }
+        Poll::Pending
+    }
+}
+
+impl<T> Drop for Join<'_, T> {
+    fn drop(&mut self) {
+        self.cell.wakers.remove(self.id);
+    }
+}
+
+/// used by the launchers: runs a flow future and hands its outcome to `cell`
+/// A task dropped before the future resolved finishes the join with an error instead of leaving
+/// it waiting forever, `JoinError::Panicked` if a poll of the future unwound.
+pub(crate) struct Deliver<C, F>
+where
+    C: Deref<Target = JoinCell<F::Output>>,
+    F: Future,
+{
+    cell: C,
+    future: F,
+    /// set while `future` is polled, still set if the poll unwound
+    polling: bool,
+    done: bool,
+}
+
+impl<C, F> Deliver<C, F>
+where
+    C: Deref<Target = JoinCell<F::Output>>,
+    F: Future,
+{
+    pub(crate) fn new(cell: C, future: F) -> Self {
+        Self {
+            cell,
+            future,
+            polling: false,
+            done: false,
+        }
+    }
+}
+
+impl<C, F> Future for Deliver<C, F>
+where
+    C: Deref<Target = JoinCell<F::Output>>,
+    F: Future,
+{
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let this = unsafe { self.get_unchecked_mut() };
+        if this.done {
+            return Poll::Ready(());
+        }
+        // the future is never moved out of `Deliver`
+        let future = unsafe { Pin::new_unchecked(&mut this.future) };
+        this.polling = true;
+        let output = future.poll(cx);
+        this.polling = false;
+        let Poll::Ready(output) = output else {
+            return Poll::Pending;
+        };
+        this.done = true;
+        this.cell.set(output);
+        Poll::Ready(())
+    }
+}
+
+impl<C, F> Drop for Deliver<C, F>
+where
+    C: Deref<Target = JoinCell<F::Output>>,
+    F: Future,
+{
+    fn drop(&mut self) {
+        if !self.done {
+            self.cell.abandon(if self.polling {
+                JoinError::Panicked
+            } else {
+                JoinError::Dropped
+            });
+        }
+    }
+}
+
+/// What the launcher generated by `#[flows::flow]` hands back
+pub struct Launched<
+    T: 'static,
+    U: 'static,
+    UD: 'static,
+    FD: 'static,
+    const CHAN_N: usize,
+    const DATA_N: usize,
+    Q: 'static = (),
+    ST: State = FlowState,
+    H: 'static = FlowEventHandler,
+    S = (),
+    const JOURNAL_N: usize = JOURNAL_CAPACITY,
+> {
+    pub ctrl: UserController<U, CHAN_N, Q, ST, H, JOURNAL_N>,
+    pub data: UserDataHandle<UD, FD, DATA_N>,
+    /// resolves to the `FlowOutcome` once the flow ends, or to a `JoinError` if it never got it
+    pub join: Join<'static, T>,
+    /// whatever the runtime's spawner handed back for the flow's task
+    /// Some runtimes cancel the task when it is dropped, e.g. smol, keep it around for as long as the flow runs
+    pub task: S,
+}
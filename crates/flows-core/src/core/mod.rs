@@ -1,17 +1,55 @@
+pub mod command;
+#[cfg(feature = "alloc")]
+pub mod compose;
 pub mod control;
 pub mod data;
+#[cfg(feature = "alloc")]
+pub mod family;
 pub mod flow;
+#[cfg(feature = "alloc")]
+pub mod handle;
 pub mod handler;
+#[cfg(feature = "journal")]
+pub mod journal;
+pub mod launch;
+mod lock;
+pub mod query;
+pub mod shared;
 pub mod slot;
+pub mod state;
+#[cfg(feature = "alloc")]
+pub mod supervisor;
+mod trace;
 pub mod traits;
 pub mod waker;
 
-pub use control::{BaseController, FlowFutureController, FnController, UserController};
-pub use data::{DataChannel, FnDataHandle, UserDataHandle};
-pub use flow::Flow;
+pub use command::{Acks, CommandError};
+#[cfg(feature = "alloc")]
+pub use compose::{Composite, Member, join_all, select, sequence};
+pub use control::{
+    BaseController, Command, FlowFutureController, FnController, UserController, UserQueryFuture,
+};
+pub use data::{DataChannel, DataError, Dropped, FnDataHandle, Overflow, UserDataHandle};
+pub use flow::{Done, Flow, FlowOutcome, FlowOutput, OutcomeOf};
+#[cfg(feature = "alloc")]
+pub use handle::FlowHandle;
 pub use handler::{
     FlowEvent, FlowEventHandler, FlowState, FnControlEvent, Handler, UserControlEvent,
 };
-pub use slot::Slot;
+#[cfg(feature = "journal")]
+pub use journal::{Journal, JournalEntry};
+pub use launch::{Join, JoinCell, JoinError, LaunchError, Launched};
+pub use query::{Query, QueryBook, QueryId, Reply};
+pub use shared::Shared;
+#[cfg(feature = "alloc")]
+pub use slot::ArcSlot;
+pub use slot::{FlowSlot, Slot, SlotPool};
+pub use state::{State, StateCell, StateChanges};
+#[cfg(feature = "alloc")]
+pub use supervisor::{Child, Restart, RestartPolicy, StopHandle, Strategy, Supervisor};
 pub use traits::Reset;
 pub use waker::AtomicWaker;
+
+/// How many events the journal of a flow keeps unless its controller says otherwise
+/// The last generic parameter of the controllers and slots, it does nothing without the `journal` feature
+pub const JOURNAL_CAPACITY: usize = 32;
@@ -0,0 +1,124 @@
+use super::Reset;
+use super::lock::SpinLock;
+use heapless::Vec;
+use portable_atomic::{AtomicU32, Ordering};
+
+/// Identifies one question the function asked the user
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct QueryId(pub u32);
+
+/// A question the function is waiting on, as seen by the user
+/// Queries opened with `FnController::block` carry no prompt
+#[derive(Debug, Clone)]
+pub struct Query<Q> {
+    pub id: QueryId,
+    pub prompt: Option<Q>,
+}
+
+/// User input released to the function by a transition
+pub enum Reply<U> {
+    /// answers the oldest unanswered query
+    Next(U),
+    /// answers a specific query
+    To(QueryId, U),
+}
+
+struct Entry<Q, U> {
+    id: QueryId,
+    prompt: Option<Q>,
+    answer: Option<U>,
+}
+
+/// Book keeping for the queries a function has outstanding
+/// The function opens and takes entries, the flow delivers answers, the user only reads prompts
+pub struct QueryBook<Q, U, const N: usize> {
+    entries: SpinLock<Vec<Entry<Q, U>, N>>,
+    next_id: AtomicU32,
+}
+
+impl<Q, U, const N: usize> Default for QueryBook<Q, U, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<Q, U, const N: usize> Reset for QueryBook<Q, U, N> {
+    fn reset(&self) {
+        self.entries.lock().clear();
+    }
+}
+
+impl<Q, U, const N: usize> QueryBook<Q, U, N> {
+    pub const fn new() -> Self {
+        QueryBook {
+            entries: SpinLock::new(Vec::new()),
+            next_id: AtomicU32::new(0),
+        }
+    }
+
+    /// Open a new query, handing the prompt back if the book is full
+    pub fn open(&self, prompt: Option<Q>) -> Result<QueryId, Option<Q>> {
+        let mut entries = self.entries.lock();
+        if entries.is_full() {
+            return Err(prompt);
+        }
+        let id = QueryId(self.next_id.fetch_add(1, Ordering::Relaxed));
+        let _ = entries.push(Entry {
+            id,
+            prompt,
+            answer: None,
+        });
+        Ok(id)
+    }
+
+    /// Store an answer for the query it belongs to
+    /// Hands the reply back if there was no unanswered query to receive it
+    pub fn deliver(&self, reply: Reply<U>) -> Result<(), Reply<U>> {
+        let mut entries = self.entries.lock();
+        let entry = match &reply {
+            Reply::Next(_) => entries.iter_mut().find(|e| e.answer.is_none()),
+            Reply::To(id, _) => entries
+                .iter_mut()
+                .find(|e| e.id == *id && e.answer.is_none()),
+        };
+        match (entry, reply) {
+            (Some(entry), Reply::Next(input) | Reply::To(_, input)) => {
+                entry.answer = Some(input);
+                Ok(())
+            }
+            (None, reply) => Err(reply),
+        }
+    }
+
+    /// Remove an answered query and return its answer
+    pub fn take(&self, id: QueryId) -> Option<U> {
+        let mut entries = self.entries.lock();
+        let index = entries
+            .iter()
+            .position(|e| e.id == id && e.answer.is_some())?;
+        entries.remove(index).answer
+    }
+
+    /// Drop a query whether or not it was answered
+    pub fn close(&self, id: QueryId) {
+        let mut entries = self.entries.lock();
+        if let Some(index) = entries.iter().position(|e| e.id == id) {
+            entries.remove(index);
+        }
+    }
+
+    /// Whether the query is still waiting for an answer
+    pub fn is_pending(&self, id: QueryId) -> bool {
+        self.entries
+            .lock()
+            .iter()
+            .any(|e| e.id == id && e.answer.is_none())
+    }
+
+    /// Visit every unanswered query, oldest first
+    pub fn for_each_pending(&self, mut f: impl FnMut(QueryId, Option<&Q>)) {
+        for entry in self.entries.lock().iter().filter(|e| e.answer.is_none()) {
+            f(entry.id, entry.prompt.as_ref());
+        }
+    }
+}
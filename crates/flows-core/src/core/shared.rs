@@ -0,0 +1,46 @@
+#[cfg(feature = "alloc")]
+use alloc::sync::Arc;
+use core::ops::Deref;
+
+/// How controllers and data handles hold on to the parts of their slot
+/// Either borrowed from a static slot, or shared with the other handles of a heap allocated one
+pub enum Shared<T: ?Sized + 'static> {
+    Static(&'static T),
+    #[cfg(feature = "alloc")]
+    Arc(Arc<T>),
+}
+
+impl<T: ?Sized> Clone for Shared<T> {
+    fn clone(&self) -> Self {
+        match self {
+            Shared::Static(inner) => Shared::Static(inner),
+            #[cfg(feature = "alloc")]
+            Shared::Arc(inner) => Shared::Arc(inner.clone()),
+        }
+    }
+}
+
+impl<T: ?Sized> Deref for Shared<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        match self {
+            Shared::Static(inner) => inner,
+            #[cfg(feature = "alloc")]
+            Shared::Arc(inner) => inner,
+        }
+    }
+}
+
+impl<T: ?Sized> From<&'static T> for Shared<T> {
+    fn from(inner: &'static T) -> Self {
+        Shared::Static(inner)
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<T: ?Sized> From<Arc<T>> for Shared<T> {
+    fn from(inner: Arc<T>) -> Self {
+        Shared::Arc(inner)
+    }
+}
@@ -1,27 +1,148 @@
 use super::{
-    BaseController, DataChannel, FlowFutureController, FnController, FnDataHandle, Reset,
+    BaseController, DataChannel, FlowEvent, FlowEventHandler, FlowFutureController, FlowState,
+    FnController, FnDataHandle, Handler, JOURNAL_CAPACITY, Overflow, Reply, Reset, Shared, State,
     UserController, UserDataHandle,
 };
 use crate::runtime::FlowRuntime;
+#[cfg(feature = "alloc")]
+use alloc::sync::Arc;
+use core::mem::MaybeUninit;
+use core::ptr;
+use portable_atomic::{AtomicU8, Ordering};
 
-pub struct Slot<U: 'static, UD: 'static, FD: 'static, const CHAN_N: usize, const DATA_N: usize> {
-    ctrl: BaseController<U, CHAN_N>,
+/// The function, flow future and user controllers handed out by a slot
+pub type Controllers<
+    R,
+    U,
+    const CHAN_N: usize,
+    Q = (),
+    ST = FlowState,
+    H = FlowEventHandler,
+    const JOURNAL_N: usize = JOURNAL_CAPACITY,
+> = (
+    FnController<R, U, CHAN_N, Q, ST, H, JOURNAL_N>,
+    FlowFutureController<U, CHAN_N, Q, ST, H, JOURNAL_N>,
+    UserController<U, CHAN_N, Q, ST, H, JOURNAL_N>,
+);
+
+/// Everything a slot hands out for one run of a flow, see `FlowSlot`
+pub type SlotParts<
+    R,
+    U,
+    UD,
+    FD,
+    const CHAN_N: usize,
+    const DATA_N: usize,
+    Q = (),
+    ST = FlowState,
+    H = FlowEventHandler,
+    const JOURNAL_N: usize = JOURNAL_CAPACITY,
+> = (
+    Controllers<R, U, CHAN_N, Q, ST, H, JOURNAL_N>,
+    (FnDataHandle<UD, FD, DATA_N>, UserDataHandle<UD, FD, DATA_N>),
+);
+
+/// Anything a flow can be spawned into with `FlowRuntime::spawn_flow`
+/// The slot has to be new or reset, e.g. handed out by `SlotPool::acquire`
+pub trait FlowSlot<
+    U: 'static,
+    UD: 'static,
+    FD: 'static,
+    const CHAN_N: usize,
+    const DATA_N: usize,
+    Q: 'static = (),
+    ST: State = FlowState,
+    H: 'static = FlowEventHandler,
+    const JOURNAL_N: usize = JOURNAL_CAPACITY,
+>
+{
+    fn parts<R: FlowRuntime>(
+        self,
+        runtime: &R,
+    ) -> SlotParts<R, U, UD, FD, CHAN_N, DATA_N, Q, ST, H, JOURNAL_N>;
+}
+
+pub struct Slot<
+    U: 'static,
+    UD: 'static,
+    FD: 'static,
+    const CHAN_N: usize,
+    const DATA_N: usize,
+    Q: 'static = (),
+    ST: State = FlowState,
+    H: 'static = FlowEventHandler,
+    const JOURNAL_N: usize = JOURNAL_CAPACITY,
+> {
+    ctrl: BaseController<U, CHAN_N, Q, ST, H, JOURNAL_N>,
     data: DataChannel<UD, FD, DATA_N>,
 }
 
-impl<U: 'static, UD: 'static, FD: 'static, const CHAN_N: usize, const DATA_N: usize> Default
-    for Slot<U, UD, FD, CHAN_N, DATA_N>
+impl<
+    U: 'static,
+    UD: 'static,
+    FD: 'static,
+    const CHAN_N: usize,
+    const DATA_N: usize,
+    Q: 'static,
+    ST: State,
+    H: Default,
+    const JOURNAL_N: usize,
+> Default for Slot<U, UD, FD, CHAN_N, DATA_N, Q, ST, H, JOURNAL_N>
 {
     fn default() -> Self {
+        Self::with_handler(H::default())
+    }
+}
+
+impl<
+    U: 'static,
+    UD: 'static,
+    FD: 'static,
+    const CHAN_N: usize,
+    const DATA_N: usize,
+    Q: 'static,
+    ST: State,
+    const JOURNAL_N: usize,
+> Slot<U, UD, FD, CHAN_N, DATA_N, Q, ST, FlowEventHandler, JOURNAL_N>
+{
+    /// A slot for flows with the default handler, usable in a `static`
+    pub const fn new() -> Self {
+        Self::with_handler(FlowEventHandler {})
+    }
+}
+
+impl<
+    U: 'static,
+    UD: 'static,
+    FD: 'static,
+    const CHAN_N: usize,
+    const DATA_N: usize,
+    Q: 'static,
+    ST: State,
+    H: 'static,
+    const JOURNAL_N: usize,
+> Slot<U, UD, FD, CHAN_N, DATA_N, Q, ST, H, JOURNAL_N>
+{
+    /// A slot whose flows transition with `handler`, usable in a `static`
+    pub const fn with_handler(handler: H) -> Self {
         Slot {
-            ctrl: BaseController::default(),
-            data: DataChannel::default(),
+            ctrl: BaseController::with_handler(handler),
+            data: DataChannel::new(),
         }
     }
 }
 
-impl<U: 'static, UD: 'static, FD: 'static, const CHAN_N: usize, const DATA_N: usize> Reset
-    for Slot<U, UD, FD, CHAN_N, DATA_N>
+impl<
+    U: 'static,
+    UD: 'static,
+    FD: 'static,
+    const CHAN_N: usize,
+    const DATA_N: usize,
+    Q: 'static,
+    ST: State,
+    H,
+    const JOURNAL_N: usize,
+> Reset for Slot<U, UD, FD, CHAN_N, DATA_N, Q, ST, H, JOURNAL_N>
 {
     fn reset(&self) {
         self.ctrl.reset();
@@ -29,30 +150,407 @@ impl<U: 'static, UD: 'static, FD: 'static, const CHAN_N: usize, const DATA_N: us
     }
 }
 
-impl<U: 'static, UD: 'static, FD: 'static, const CHAN_N: usize, const DATA_N: usize>
-    Slot<U, UD, FD, CHAN_N, DATA_N>
+impl<
+    U: 'static,
+    UD: 'static,
+    FD: 'static,
+    const CHAN_N: usize,
+    const DATA_N: usize,
+    Q: 'static,
+    ST: State,
+    H: 'static,
+    const JOURNAL_N: usize,
+> Slot<U, UD, FD, CHAN_N, DATA_N, Q, ST, H, JOURNAL_N>
+where
+    H: Handler<ST, FlowEvent<U>, Output = Reply<U>>,
 {
+    /// Set what pushing to a full data queue does, for each direction
+    pub fn set_overflow(&self, user_data: Overflow, fn_data: Overflow) {
+        self.data.set_overflow(user_data, fn_data);
+    }
+
     pub fn handles(
         &'static self,
     ) -> (FnDataHandle<UD, FD, DATA_N>, UserDataHandle<UD, FD, DATA_N>) {
         (
-            FnDataHandle::new(&self.data.fn_data, &self.data.user_data),
-            UserDataHandle::new(&self.data.user_data, &self.data.fn_data),
+            FnDataHandle::new(Shared::Static(&self.data)),
+            UserDataHandle::new(Shared::Static(&self.data)),
         )
     }
 
     pub fn ctrls<R: FlowRuntime>(
         &'static self,
-        runtime: &'static R,
-    ) -> (
-        FnController<R, U, CHAN_N>,
-        FlowFutureController<U, CHAN_N>,
-        UserController<U, CHAN_N>,
-    ) {
+        runtime: &R,
+    ) -> Controllers<R, U, CHAN_N, Q, ST, H, JOURNAL_N> {
+        (
+            FnController::new(Shared::Static(&self.ctrl), runtime.clone()),
+            FlowFutureController::new(Shared::Static(&self.ctrl)),
+            UserController::new(Shared::Static(&self.ctrl)),
+        )
+    }
+}
+
+impl<
+    U: 'static,
+    UD: 'static,
+    FD: 'static,
+    const CHAN_N: usize,
+    const DATA_N: usize,
+    Q: 'static,
+    ST: State,
+    H: 'static,
+    const JOURNAL_N: usize,
+> FlowSlot<U, UD, FD, CHAN_N, DATA_N, Q, ST, H, JOURNAL_N>
+    for &'static Slot<U, UD, FD, CHAN_N, DATA_N, Q, ST, H, JOURNAL_N>
+where
+    H: Handler<ST, FlowEvent<U>, Output = Reply<U>>,
+{
+    fn parts<R: FlowRuntime>(
+        self,
+        runtime: &R,
+    ) -> SlotParts<R, U, UD, FD, CHAN_N, DATA_N, Q, ST, H, JOURNAL_N> {
+        (self.ctrls(runtime), self.handles())
+    }
+}
+
+/// Heap allocated slot for flows created at runtime, e.g. one per request or session
+/// The controllers and data handles share ownership of it, it is freed once the last one drops
+#[cfg(feature = "alloc")]
+pub struct ArcSlot<
+    U: 'static,
+    UD: 'static,
+    FD: 'static,
+    const CHAN_N: usize,
+    const DATA_N: usize,
+    Q: 'static = (),
+    ST: State = FlowState,
+    H: 'static = FlowEventHandler,
+    const JOURNAL_N: usize = JOURNAL_CAPACITY,
+> {
+    ctrl: Arc<BaseController<U, CHAN_N, Q, ST, H, JOURNAL_N>>,
+    data: Arc<DataChannel<UD, FD, DATA_N>>,
+}
+
+#[cfg(feature = "alloc")]
+impl<
+    U: 'static,
+    UD: 'static,
+    FD: 'static,
+    const CHAN_N: usize,
+    const DATA_N: usize,
+    Q: 'static,
+    ST: State,
+    H: Default,
+    const JOURNAL_N: usize,
+> Default for ArcSlot<U, UD, FD, CHAN_N, DATA_N, Q, ST, H, JOURNAL_N>
+{
+    fn default() -> Self {
+        ArcSlot {
+            ctrl: Arc::new(BaseController::default()),
+            data: Arc::new(DataChannel::default()),
+        }
+    }
+}
+
+/// Another handle to the same slot, e.g. to keep the user side of a flow that is restarted in it
+#[cfg(feature = "alloc")]
+impl<
+    U: 'static,
+    UD: 'static,
+    FD: 'static,
+    const CHAN_N: usize,
+    const DATA_N: usize,
+    Q: 'static,
+    ST: State,
+    H: 'static,
+    const JOURNAL_N: usize,
+> Clone for ArcSlot<U, UD, FD, CHAN_N, DATA_N, Q, ST, H, JOURNAL_N>
+{
+    fn clone(&self) -> Self {
+        ArcSlot {
+            ctrl: self.ctrl.clone(),
+            data: self.data.clone(),
+        }
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<
+    U: 'static,
+    UD: 'static,
+    FD: 'static,
+    const CHAN_N: usize,
+    const DATA_N: usize,
+    Q: 'static,
+    ST: State,
+    H,
+    const JOURNAL_N: usize,
+> Reset for ArcSlot<U, UD, FD, CHAN_N, DATA_N, Q, ST, H, JOURNAL_N>
+{
+    fn reset(&self) {
+        self.ctrl.reset();
+        self.data.reset();
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<
+    U: 'static,
+    UD: 'static,
+    FD: 'static,
+    const CHAN_N: usize,
+    const DATA_N: usize,
+    Q: 'static,
+    ST: State,
+    H: 'static,
+    const JOURNAL_N: usize,
+> ArcSlot<U, UD, FD, CHAN_N, DATA_N, Q, ST, H, JOURNAL_N>
+where
+    H: Handler<ST, FlowEvent<U>, Output = Reply<U>> + Default,
+{
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set what pushing to a full data queue does, for each direction
+    pub fn set_overflow(&self, user_data: Overflow, fn_data: Overflow) {
+        self.data.set_overflow(user_data, fn_data);
+    }
+
+    pub fn handles(&self) -> (FnDataHandle<UD, FD, DATA_N>, UserDataHandle<UD, FD, DATA_N>) {
+        (
+            FnDataHandle::new(Shared::Arc(self.data.clone())),
+            UserDataHandle::new(Shared::Arc(self.data.clone())),
+        )
+    }
+
+    pub fn ctrls<R: FlowRuntime>(
+        &self,
+        runtime: &R,
+    ) -> Controllers<R, U, CHAN_N, Q, ST, H, JOURNAL_N> {
         (
-            FnController::new(&self.ctrl, runtime),
-            FlowFutureController::new(&self.ctrl),
-            UserController::new(&self.ctrl),
+            FnController::new(Shared::Arc(self.ctrl.clone()), runtime.clone()),
+            FlowFutureController::new(Shared::Arc(self.ctrl.clone())),
+            UserController::new(Shared::Arc(self.ctrl.clone())),
         )
     }
+
+    /// used by `FnController::spawn_child` to link the child flow to its parent
+    pub(crate) fn base(&self) -> Arc<BaseController<U, CHAN_N, Q, ST, H, JOURNAL_N>> {
+        self.ctrl.clone()
+    }
+
+    /// The user controller of the flow currently in the slot
+    /// A reset cuts it off, ask again for the flow that runs next
+    pub fn user(&self) -> UserController<U, CHAN_N, Q, ST, H, JOURNAL_N> {
+        UserController::new(Shared::Arc(self.ctrl.clone()))
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<
+    U: 'static,
+    UD: 'static,
+    FD: 'static,
+    const CHAN_N: usize,
+    const DATA_N: usize,
+    Q: 'static,
+    ST: State,
+    H: 'static,
+    const JOURNAL_N: usize,
+> FlowSlot<U, UD, FD, CHAN_N, DATA_N, Q, ST, H, JOURNAL_N>
+    for &ArcSlot<U, UD, FD, CHAN_N, DATA_N, Q, ST, H, JOURNAL_N>
+where
+    H: Handler<ST, FlowEvent<U>, Output = Reply<U>> + Default,
+{
+    fn parts<R: FlowRuntime>(
+        self,
+        runtime: &R,
+    ) -> SlotParts<R, U, UD, FD, CHAN_N, DATA_N, Q, ST, H, JOURNAL_N> {
+        (self.ctrls(runtime), self.handles())
+    }
+}
+
+/// The handles share ownership of the slot, it does not have to be kept around
+#[cfg(feature = "alloc")]
+impl<
+    U: 'static,
+    UD: 'static,
+    FD: 'static,
+    const CHAN_N: usize,
+    const DATA_N: usize,
+    Q: 'static,
+    ST: State,
+    H: 'static,
+    const JOURNAL_N: usize,
+> FlowSlot<U, UD, FD, CHAN_N, DATA_N, Q, ST, H, JOURNAL_N>
+    for ArcSlot<U, UD, FD, CHAN_N, DATA_N, Q, ST, H, JOURNAL_N>
+where
+    H: Handler<ST, FlowEvent<U>, Output = Reply<U>> + Default,
+{
+    fn parts<R: FlowRuntime>(
+        self,
+        runtime: &R,
+    ) -> SlotParts<R, U, UD, FD, CHAN_N, DATA_N, Q, ST, H, JOURNAL_N> {
+        (&self).parts(runtime)
+    }
+}
+
+const FREE: u8 = 0;
+const LEASED: u8 = 1;
+const RECLAIMING: u8 = 2;
+
+/// Fixed number of slots handed out to flows, for targets without a heap
+/// A slot goes back to the pool once its flow reaches a terminal state. It is reset when it is
+/// handed out again, which starts a new generation and cuts off the handles of the previous flow.
+/// A flow that is handed a slot has to be run until it ends, cancel it if it is not needed.
+///
+/// ```ignore
+/// static POOL: SlotPool<4, (), (), u32, 4, 8> = SlotPool::new();
+/// ```
+pub struct SlotPool<
+    const N: usize,
+    U: 'static,
+    UD: 'static,
+    FD: 'static,
+    const CHAN_N: usize,
+    const DATA_N: usize,
+    Q: 'static = (),
+    ST: State = FlowState,
+    H: 'static = FlowEventHandler,
+    const JOURNAL_N: usize = JOURNAL_CAPACITY,
+> {
+    slots: [Slot<U, UD, FD, CHAN_N, DATA_N, Q, ST, H, JOURNAL_N>; N],
+    status: [AtomicU8; N],
+}
+
+impl<
+    const N: usize,
+    U: 'static,
+    UD: 'static,
+    FD: 'static,
+    const CHAN_N: usize,
+    const DATA_N: usize,
+    Q: 'static,
+    ST: State,
+    H: Default,
+    const JOURNAL_N: usize,
+> Default for SlotPool<N, U, UD, FD, CHAN_N, DATA_N, Q, ST, H, JOURNAL_N>
+{
+    fn default() -> Self {
+        SlotPool {
+            slots: core::array::from_fn(|_| Slot::default()),
+            status: [const { AtomicU8::new(FREE) }; N],
+        }
+    }
+}
+
+impl<
+    const N: usize,
+    U: 'static,
+    UD: 'static,
+    FD: 'static,
+    const CHAN_N: usize,
+    const DATA_N: usize,
+    Q: 'static,
+    ST: State,
+    const JOURNAL_N: usize,
+> SlotPool<N, U, UD, FD, CHAN_N, DATA_N, Q, ST, FlowEventHandler, JOURNAL_N>
+{
+    /// A pool of slots for flows with the default handler, usable in a `static`
+    pub const fn new() -> Self {
+        SlotPool {
+            slots: [const { Slot::new() }; N],
+            status: [const { AtomicU8::new(FREE) }; N],
+        }
+    }
+}
+
+impl<
+    const N: usize,
+    U: 'static,
+    UD: 'static,
+    FD: 'static,
+    const CHAN_N: usize,
+    const DATA_N: usize,
+    Q: 'static,
+    ST: State,
+    H: 'static + Copy,
+    const JOURNAL_N: usize,
+> SlotPool<N, U, UD, FD, CHAN_N, DATA_N, Q, ST, H, JOURNAL_N>
+{
+    /// A pool whose slots all transition with a copy of `handler`, usable in a `static`
+    pub const fn with_handler(handler: H) -> Self {
+        let mut slots = [const {
+            MaybeUninit::<Slot<U, UD, FD, CHAN_N, DATA_N, Q, ST, H, JOURNAL_N>>::uninit()
+        }; N];
+        let mut i = 0;
+        while i < N {
+            slots[i] = MaybeUninit::new(Slot::with_handler(handler));
+            i += 1;
+        }
+        SlotPool {
+            // SAFETY: every element was written above, `MaybeUninit<T>` has the layout of `T`
+            slots: unsafe { ptr::read((&raw const slots).cast()) },
+            status: [const { AtomicU8::new(FREE) }; N],
+        }
+    }
+}
+
+impl<
+    const N: usize,
+    U: 'static,
+    UD: 'static,
+    FD: 'static,
+    const CHAN_N: usize,
+    const DATA_N: usize,
+    Q: 'static,
+    ST: State,
+    H: 'static,
+    const JOURNAL_N: usize,
+> SlotPool<N, U, UD, FD, CHAN_N, DATA_N, Q, ST, H, JOURNAL_N>
+where
+    H: Handler<ST, FlowEvent<U>, Output = Reply<U>>,
+{
+    /// Hand out a slot for a new flow, `None` while all of them are taken by running flows
+    pub fn acquire(
+        &'static self,
+    ) -> Option<&'static Slot<U, UD, FD, CHAN_N, DATA_N, Q, ST, H, JOURNAL_N>> {
+        for (slot, status) in self.slots.iter().zip(&self.status) {
+            if status
+                .compare_exchange(FREE, LEASED, Ordering::AcqRel, Ordering::Relaxed)
+                .is_ok()
+            {
+                return Some(slot);
+            }
+            // keeps other callers away while the slot is checked and reset
+            if status
+                .compare_exchange(LEASED, RECLAIMING, Ordering::AcqRel, Ordering::Relaxed)
+                .is_ok()
+            {
+                let ended = slot.ctrl.state().is_terminal();
+                if ended {
+                    slot.reset();
+                }
+                status.store(LEASED, Ordering::Release);
+                if ended {
+                    return Some(slot);
+                }
+            }
+        }
+        None
+    }
+
+    /// How many slots `acquire` could hand out right now
+    pub fn available(&self) -> usize {
+        self.slots
+            .iter()
+            .zip(&self.status)
+            .filter(|(slot, status)| match status.load(Ordering::Acquire) {
+                FREE => true,
+                LEASED => slot.ctrl.state().is_terminal(),
+                _ => false,
+            })
+            .count()
+    }
 }
@@ -0,0 +1,278 @@
+use super::lock::SpinLock;
+use super::waker::WakerSet;
+use super::{FlowState, Reset};
+use core::fmt::Debug;
+use core::marker::PhantomData;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use futures_core::Stream;
+use portable_atomic::{AtomicU32, Ordering};
+
+const CODE_BITS: u32 = 9;
+const CODE_MASK: u32 = (1 << CODE_BITS) - 1;
+/// set instead of a code for a state that does not pack into a byte
+const UNPACKED: u32 = 1 << 8;
+
+/// What the flow machinery needs to know about the states of a `Handler`
+pub trait State: Clone + PartialEq + Default + Debug + 'static {
+    /// The built-in state this one behaves like
+    /// Terminal kinds end the flow, observers that do not know the custom states go by the kind
+    fn kind(&self) -> FlowState;
+
+    /// The state the flow enters when it ends as `kind`
+    /// Only ever called with `Completed`, `Cancelled` or `Error`, and with `Blocked` to show a running
+    /// flow as blocked while one of its child flows is, which only happens if the result is `Blocked`
+    fn from_kind(kind: FlowState) -> Self;
+
+    /// Pack the state into a byte for lock-free publishing, has to round trip through `from_bits`
+    /// States that do not fit, e.g. `WaitingForApproval(reason)`, keep the default `None`
+    /// and are published under a lock instead
+    fn to_bits(&self) -> Option<u8> {
+        None
+    }
+
+    /// The state `to_bits` packed into `bits`
+    fn from_bits(_bits: u8) -> Option<Self> {
+        None
+    }
+
+    /// Whether the flow can never leave this state
+    fn is_terminal(&self) -> bool {
+        self.kind().is_terminal()
+    }
+}
+
+impl State for FlowState {
+    fn kind(&self) -> FlowState {
+        self.clone()
+    }
+
+    fn from_kind(kind: FlowState) -> Self {
+        kind
+    }
+
+    fn to_bits(&self) -> Option<u8> {
+        Some(match self {
+            FlowState::Running => 0,
+            FlowState::Paused => 1,
+            FlowState::Blocked => 2,
+            FlowState::Cancelled => 3,
+            FlowState::Completed => 4,
+            FlowState::Error => 5,
+        })
+    }
+
+    fn from_bits(bits: u8) -> Option<Self> {
+        match bits {
+            0 => Some(FlowState::Running),
+            1 => Some(FlowState::Paused),
+            2 => Some(FlowState::Blocked),
+            3 => Some(FlowState::Cancelled),
+            4 => Some(FlowState::Completed),
+            5 => Some(FlowState::Error),
+            _ => None,
+        }
+    }
+}
+
+impl FlowState {
+    /// Whether the flow can never leave this state
+    pub fn is_terminal(&self) -> bool {
+        matches!(
+            self,
+            FlowState::Cancelled | FlowState::Completed | FlowState::Error
+        )
+    }
+}
+
+/// Lock-free copy of a flow's state published for the user side
+/// The state is packed with a version counter so observers can tell transitions apart,
+/// a state that does not pack into a byte is kept next to it under a lock.
+/// Every reset starts a new generation, handles of an older one see the state the previous run ended in.
+pub struct StateCell<ST: State = FlowState> {
+    bits: AtomicU32,
+    /// the last published state if it does not pack, written together with `bits`
+    unpacked: SpinLock<Option<ST>>,
+    generation: AtomicU32,
+    /// the state the previous generation ended in
+    ended: SpinLock<Option<ST>>,
+    /// the tasks polling a `StateChanges` of the cell
+    observers: WakerSet,
+    _state: PhantomData<fn() -> ST>,
+}
+
+impl<ST: State> Default for StateCell<ST> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<ST: State> Reset for StateCell<ST> {
+    fn reset(&self) {
+        *self.ended.lock() = Some(self.load());
+        // bumped before the state so that whoever sees the new state also sees the new generation
+        self.generation.fetch_add(1, Ordering::Release);
+        self.store(&ST::default());
+        // observers of the old generation get to see it ended
+        self.observers.wake();
+    }
+}
+
+impl<ST: State> StateCell<ST> {
+    /// Starts out as the state packed to 0, which `ST::default()` is expected to be if it packs
+    pub const fn new() -> Self {
+        StateCell {
+            bits: AtomicU32::new(0),
+            unpacked: SpinLock::new(None),
+            generation: AtomicU32::new(0),
+            ended: SpinLock::new(None),
+            observers: WakerSet::new(),
+            _state: PhantomData,
+        }
+    }
+
+    /// The last published state
+    pub fn load(&self) -> ST {
+        self.snapshot().1
+    }
+
+    /// The last published state with the bits it was published under
+    fn snapshot(&self) -> (u32, ST) {
+        let bits = self.bits.load(Ordering::Acquire);
+        if bits & UNPACKED == 0
+            && let Some(state) = ST::from_bits(bits as u8)
+        {
+            return (bits, state);
+        }
+        let unpacked = self.unpacked.lock();
+        // the state may have been published again before the lock was taken
+        let bits = self.bits.load(Ordering::Acquire);
+        let state = match bits & UNPACKED {
+            0 => ST::from_bits(bits as u8),
+            _ => unpacked.clone(),
+        };
+        (bits, state.unwrap_or_default())
+    }
+
+    /// The current generation, bumped by every reset
+    pub fn generation(&self) -> u32 {
+        self.generation.load(Ordering::Acquire)
+    }
+
+    /// The last published state as seen by a handle of `generation`
+    pub fn load_at(&self, generation: u32) -> ST {
+        let state = self.load();
+        if self.generation() == generation {
+            state
+        } else {
+            self.ended.lock().clone().unwrap_or_default()
+        }
+    }
+
+    /// used by the flow future to publish the state it settled in
+    /// only an actual change bumps the version and wakes the observers
+    pub fn store(&self, state: &ST) {
+        let bits = self.bits.load(Ordering::Relaxed);
+        let version = (bits >> CODE_BITS).wrapping_add(1);
+        match state.to_bits() {
+            Some(code) => {
+                if bits & CODE_MASK == code as u32 {
+                    return;
+                }
+                self.bits
+                    .store((version << CODE_BITS) | code as u32, Ordering::Release);
+            }
+            None => {
+                let mut unpacked = self.unpacked.lock();
+                if bits & UNPACKED != 0 && unpacked.as_ref() == Some(state) {
+                    return;
+                }
+                *unpacked = Some(state.clone());
+                self.bits
+                    .store((version << CODE_BITS) | UNPACKED, Ordering::Release);
+            }
+        }
+        self.observers.wake();
+    }
+
+    /// Stream of the states published from now on, ending after a terminal state
+    pub fn changes(&self) -> StateChanges<'_, ST> {
+        self.changes_at(self.generation())
+    }
+
+    /// Stream of the states published from now on for a handle of `generation`
+    pub fn changes_at(&self, generation: u32) -> StateChanges<'_, ST> {
+        let (bits, state) = self.snapshot();
+        StateChanges {
+            cell: self,
+            id: self.observers.id(),
+            generation,
+            seen: bits >> CODE_BITS,
+            // a flow that already ended has nothing left to report
+            done: self.generation() != generation || state.is_terminal(),
+        }
+    }
+}
+
+/// Stream of state transitions of a flow
+/// Transitions that happen between two polls are coalesced into the latest state.
+/// Every stream is woken on its own, a flow can have any number of observers.
+pub struct StateChanges<'a, ST: State = FlowState> {
+    cell: &'a StateCell<ST>,
+    /// what the stream registers its waker under
+    id: u32,
+    generation: u32,
+    seen: u32,
+    done: bool,
+}
+
+impl<ST: State> StateChanges<'_, ST> {
+    /// Wait for the next transition, `None` once the flow has ended
+    pub async fn next(&mut self) -> Option<ST> {
+        core::future::poll_fn(|cx| Pin::new(&mut *self).poll_next(cx)).await
+    }
+
+    fn try_next(&mut self) -> Option<ST> {
+        let bits = self.cell.bits.load(Ordering::Acquire);
+        if self.cell.generation() != self.generation {
+            // the slot was reused, the flow this stream followed ended in the meantime
+            self.done = true;
+            return Some(self.cell.load_at(self.generation));
+        }
+        if bits >> CODE_BITS == self.seen {
+            return None;
+        }
+        // transitions published since are coalesced into the latest one
+        let (bits, state) = self.cell.snapshot();
+        self.seen = bits >> CODE_BITS;
+        self.done = state.is_terminal();
+        Some(state)
+    }
+}
+
+impl<ST: State> Stream for StateChanges<'_, ST> {
+    type Item = ST;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<ST>> {
+        let this = self.get_mut();
+        if this.done {
+            return Poll::Ready(None);
+        }
+        if let Some(state) = this.try_next() {
+            return Poll::Ready(Some(state));
+        }
+
+        this.cell.observers.register(this.id, cx.waker());
+        // the flow may have published between the check and the registration
+        match this.try_next() {
+            Some(state) => Poll::Ready(Some(state)),
+            None => Poll::Pending,
+        }
+    }
+}
+
+impl<ST: State> Drop for StateChanges<'_, ST> {
+    fn drop(&mut self) {
+        self.cell.observers.remove(self.id);
+    }
+}
@@ -0,0 +1,645 @@
+use super::flow::{BoxedFlow, name_of};
+use super::{
+    ArcSlot, AtomicWaker, Flow, FlowEvent, FlowOutput, FlowSlot, FlowState, FnController,
+    FnDataHandle, Handler, Reply, Reset, State, UserDataHandle,
+};
+use crate::runtime::FlowRuntime;
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use portable_atomic::{AtomicBool, Ordering};
+
+/// When a supervised flow is started again once it ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Restart {
+    /// the flow runs once
+    Never,
+    /// the flow is restarted when it ends in an error
+    OnError,
+    /// the flow is restarted however it ends, a cancel by the user included
+    Always,
+}
+
+impl Restart {
+    /// Whether a run that ended in `state` is started again
+    pub fn applies(&self, state: &FlowState) -> bool {
+        match self {
+            Restart::Never => false,
+            Restart::OnError => *state == FlowState::Error,
+            Restart::Always => true,
+        }
+    }
+}
+
+/// Which children a supervisor restarts when one of them is restarted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Strategy {
+    /// only the child that ended
+    OneForOne,
+    /// the child that ended and every child still running or about to restart, but those that
+    /// restart `Never`, the running ones are cancelled first
+    /// A child that ended before without its own policy restarting it stays ended
+    OneForAll,
+}
+
+/// How often and how quickly a supervisor restarts its children
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct RestartPolicy {
+    /// the supervisor gives up once it would restart more often than this within `window_ms`
+    pub max_restarts: u32,
+    pub window_ms: u32,
+    /// wait before the first restart in the window, doubled for every further one
+    pub backoff_ms: u32,
+    /// the longest wait before a restart
+    pub max_backoff_ms: u32,
+}
+
+impl Default for RestartPolicy {
+    /// 3 restarts in 5 seconds, waiting 100ms up to 10s
+    fn default() -> Self {
+        Self {
+            max_restarts: 3,
+            window_ms: 5_000,
+            backoff_ms: 100,
+            max_backoff_ms: 10_000,
+        }
+    }
+}
+
+impl RestartPolicy {
+    /// The wait before the `nth` restart within the window, counting from 1
+    pub fn backoff(&self, nth: u32) -> u32 {
+        let factor = 1u32.checked_shl(nth.saturating_sub(1)).unwrap_or(u32::MAX);
+        self.backoff_ms
+            .saturating_mul(factor)
+            .min(self.max_backoff_ms)
+    }
+}
+
+/// Something a supervisor starts, drives and stops: a supervised flow or a nested supervisor
+pub trait Child: Send {
+    /// Start a new run from the beginning, the previous one has ended
+    fn start(&mut self);
+
+    /// Drive the current run, resolves to the state it ended in
+    fn poll_run(&mut self, cx: &mut Context<'_>) -> Poll<FlowState>;
+
+    /// Ask the current run to end, it resolves as cancelled on a later poll
+    fn stop(&mut self);
+
+    /// When the child is started again once a run ended
+    fn restart(&self) -> Restart;
+}
+
+/// A flow function with its init value, run in its slot again for every restart
+struct Supervised<
+    R: FlowRuntime,
+    Fun,
+    I,
+    F: Future,
+    U: 'static,
+    UD: 'static,
+    FD: 'static,
+    const CHAN_N: usize,
+    const DATA_N: usize,
+    Q: 'static,
+    ST: State,
+    H: 'static,
+    const JOURNAL_N: usize,
+> {
+    runtime: R,
+    function: Fun,
+    init: I,
+    slot: ArcSlot<U, UD, FD, CHAN_N, DATA_N, Q, ST, H, JOURNAL_N>,
+    restart: Restart,
+    run: Option<BoxedFlow<F, U, CHAN_N, Q, ST, H, JOURNAL_N>>,
+    /// the user's end of the data channel of the current run, unless `on_run` took it
+    data: Option<UserDataHandle<UD, FD, DATA_N>>,
+    on_run: Option<OnRun<UD, FD, DATA_N>>,
+    /// the slot only needs a reset once a run used it
+    used: bool,
+}
+
+/// Gets the user's end of the data channel of every run, see `Supervisor::supervise_with_data`
+type OnRun<UD, FD, const DATA_N: usize> = Box<dyn FnMut(UserDataHandle<UD, FD, DATA_N>) + Send>;
+
+impl<
+    R,
+    Fun,
+    I,
+    F,
+    U,
+    UD,
+    FD,
+    const CHAN_N: usize,
+    const DATA_N: usize,
+    Q,
+    ST,
+    H,
+    const JOURNAL_N: usize,
+> Child for Supervised<R, Fun, I, F, U, UD, FD, CHAN_N, DATA_N, Q, ST, H, JOURNAL_N>
+where
+    R: FlowRuntime,
+    Fun: Fn(I, FnController<R, U, CHAN_N, Q, ST, H, JOURNAL_N>, FnDataHandle<UD, FD, DATA_N>) -> F
+        + Send,
+    I: Clone + Send,
+    F: Future,
+    F::Output: FlowOutput,
+    Flow<F, U, CHAN_N, Q, ST, H, JOURNAL_N>: Send,
+    ArcSlot<U, UD, FD, CHAN_N, DATA_N, Q, ST, H, JOURNAL_N>: Send,
+    UserDataHandle<UD, FD, DATA_N>: Send,
+    ST: State,
+    H: Handler<ST, FlowEvent<U>, Output = Reply<U>> + Default,
+{
+    fn start(&mut self) {
+        // the previous run has to be gone before its slot is reset
+        self.run = None;
+        if self.used {
+            self.slot.reset();
+        }
+        // a handle of the previous run drops without closing the channel of the next one
+        self.data = None;
+        self.used = true;
+        let ((fn_ctrl, flow_ctrl, _), (fn_data, user_data)) =
+            self.slot.clone().parts(&self.runtime);
+        // dropping the user's end would close the channel, it is kept for the whole run
+        match self.on_run.as_mut() {
+            Some(on_run) => on_run(user_data),
+            None => self.data = Some(user_data),
+        }
+        let future = (self.function)(self.init.clone(), fn_ctrl, fn_data);
+        let flow = Flow::new(future, flow_ctrl).with_name(name_of::<Fun>());
+        self.run = Some(Box::pin(flow));
+    }
+
+    fn poll_run(&mut self, cx: &mut Context<'_>) -> Poll<FlowState> {
+        let Some(run) = self.run.as_mut() else {
+            return Poll::Ready(FlowState::Cancelled);
+        };
+        let outcome = core::task::ready!(run.as_mut().poll(cx));
+        // the supervisor only cares how the run ended, the value is dropped with it
+        self.run = None;
+        Poll::Ready(outcome.state())
+    }
+
+    fn stop(&mut self) {
+        if !self.slot.user().cancel().is_sent() {
+            // the run is dropped as it is, it resolves as cancelled
+            self.run = None;
+        }
+    }
+
+    fn restart(&self) -> Restart {
+        self.restart
+    }
+}
+
+/// Where a child of a supervisor is at
+enum Status<D> {
+    /// not started yet
+    Idle,
+    Running,
+    /// waiting out the backoff before its restart
+    Waiting(Pin<Box<D>>),
+    /// asked to end by the supervisor, still running until it does
+    Stopping,
+    /// ended because the supervisor stopped it, or it ended and its policy restarts it
+    Stopped,
+    /// ended for good as far as the child is concerned
+    Ended,
+}
+
+/// A child of a supervisor with where it is at
+type Entry<D> = (Box<dyn Child>, Status<D>);
+
+/// Why a supervisor is stopping all of its children
+#[derive(Clone, Copy, PartialEq)]
+enum Stop {
+    /// to restart them together after `backoff_ms`
+    Restart(u32),
+    /// it restarted too often
+    GiveUp,
+    /// its own supervisor or the user stopped it
+    Cancel,
+}
+
+/// Lets the user stop a supervisor from outside, see `Supervisor::stop_handle`
+#[derive(Clone, Default)]
+pub struct StopHandle {
+    inner: Arc<StopSignal>,
+}
+
+#[derive(Default)]
+struct StopSignal {
+    requested: AtomicBool,
+    waker: AtomicWaker,
+}
+
+impl StopHandle {
+    /// Cancel every child, the supervisor resolves to `Cancelled` once they ended
+    /// A nested supervisor that is stopped may be restarted by its own supervisor, as a flow is
+    pub fn stop(&self) {
+        self.inner.requested.store(true, Ordering::Release);
+        self.inner.waker.wake();
+    }
+}
+
+/// Runs flows and restarts them as they end, see `Restart` and `RestartPolicy`
+/// Await it or spawn it, it resolves once every child has ended for good: `Completed`, or `Error`
+/// once it restarted too often within the window, or `Cancelled` once stopped through its
+/// `StopHandle` or by its own supervisor.
+/// The children are polled from the supervisor's task.
+///
+/// ```ignore
+/// let workers = Supervisor::new(runtime.clone(), Strategy::OneForAll)
+///     .supervise(Restart::Always, worker, 1, ArcSlot::new())
+///     .supervise(Restart::Always, worker, 2, ArcSlot::new());
+/// let root = Supervisor::new(runtime.clone(), Strategy::OneForOne)
+///     .supervise(Restart::OnError, job, init, slot.clone())
+///     .with_child(workers);
+/// let stop = root.stop_handle();
+/// runtime.spawn(async move { root.await; });
+/// // the user side of the run currently in the slot
+/// slot.user().pause().await?;
+/// // cancels every flow below the root
+/// stop.stop();
+/// ```
+pub struct Supervisor<R: FlowRuntime> {
+    runtime: R,
+    strategy: Strategy,
+    policy: RestartPolicy,
+    restart: Restart,
+    children: Vec<Entry<R::DelayFuture>>,
+    /// when the restarts within the window happened, oldest first
+    restarts: VecDeque<u64>,
+    stopping: Option<Stop>,
+    stop: StopHandle,
+}
+
+// the runtime is never pinned, the children and delays are boxed
+impl<R: FlowRuntime> Unpin for Supervisor<R> {}
+
+impl<R: FlowRuntime> Supervisor<R> {
+    pub fn new(runtime: R, strategy: Strategy) -> Self {
+        Self {
+            runtime,
+            strategy,
+            policy: RestartPolicy::default(),
+            restart: Restart::OnError,
+            children: Vec::new(),
+            restarts: VecDeque::new(),
+            stopping: None,
+            stop: StopHandle::default(),
+        }
+    }
+
+    /// A handle to stop the supervisor with, e.g. once it is spawned
+    pub fn stop_handle(&self) -> StopHandle {
+        self.stop.clone()
+    }
+
+    pub fn with_policy(mut self, policy: RestartPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// When this supervisor is restarted once it ended, if it is the child of another one
+    /// `OnError` by default, i.e. once it gave up on its own children
+    pub fn with_restart(mut self, restart: Restart) -> Self {
+        self.restart = restart;
+        self
+    }
+
+    /// Supervise any child, e.g. a nested supervisor
+    pub fn with_child(mut self, child: impl Child + 'static) -> Self {
+        self.children.push((Box::new(child), Status::Idle));
+        self
+    }
+
+    /// Supervise `function` run with a clone of `init` in `slot`, which is reset for every restart
+    /// Keep a clone of the slot to reach the user side of the run currently in it
+    #[allow(clippy::type_complexity)]
+    pub fn supervise<
+        Fun,
+        I,
+        F,
+        U,
+        UD,
+        FD,
+        const CHAN_N: usize,
+        const DATA_N: usize,
+        Q,
+        ST,
+        H,
+        const JOURNAL_N: usize,
+    >(
+        self,
+        restart: Restart,
+        function: Fun,
+        init: I,
+        slot: ArcSlot<U, UD, FD, CHAN_N, DATA_N, Q, ST, H, JOURNAL_N>,
+    ) -> Self
+    where
+        Fun: Fn(
+                I,
+                FnController<R, U, CHAN_N, Q, ST, H, JOURNAL_N>,
+                FnDataHandle<UD, FD, DATA_N>,
+            ) -> F
+            + Send
+            + 'static,
+        I: Clone + Send + 'static,
+        F: Future + 'static,
+        F::Output: FlowOutput,
+        Flow<F, U, CHAN_N, Q, ST, H, JOURNAL_N>: Send,
+        ArcSlot<U, UD, FD, CHAN_N, DATA_N, Q, ST, H, JOURNAL_N>: Send,
+        UserDataHandle<UD, FD, DATA_N>: Send,
+        U: 'static,
+        UD: 'static,
+        FD: 'static,
+        Q: 'static,
+        ST: State,
+        H: Handler<ST, FlowEvent<U>, Output = Reply<U>> + Default + 'static,
+    {
+        self.supervised(restart, function, init, slot, None)
+    }
+
+    /// Like `supervise`, handing `on_run` the user's end of the data channel of every run
+    /// The handle of a run is cut off once the slot is reset for the next one.
+    ///
+    /// ```ignore
+    /// let (tx, rx) = std::sync::mpsc::channel();
+    /// let supervisor = Supervisor::new(runtime.clone(), Strategy::OneForOne)
+    ///     .supervise_with_data(Restart::OnError, sensor, init, slot, move |data| {
+    ///         let _ = tx.send(data);
+    ///     });
+    /// ```
+    #[allow(clippy::type_complexity)]
+    pub fn supervise_with_data<
+        Fun,
+        I,
+        F,
+        U,
+        UD,
+        FD,
+        const CHAN_N: usize,
+        const DATA_N: usize,
+        Q,
+        ST,
+        H,
+        const JOURNAL_N: usize,
+    >(
+        self,
+        restart: Restart,
+        function: Fun,
+        init: I,
+        slot: ArcSlot<U, UD, FD, CHAN_N, DATA_N, Q, ST, H, JOURNAL_N>,
+        on_run: impl FnMut(UserDataHandle<UD, FD, DATA_N>) + Send + 'static,
+    ) -> Self
+    where
+        Fun: Fn(
+                I,
+                FnController<R, U, CHAN_N, Q, ST, H, JOURNAL_N>,
+                FnDataHandle<UD, FD, DATA_N>,
+            ) -> F
+            + Send
+            + 'static,
+        I: Clone + Send + 'static,
+        F: Future + 'static,
+        F::Output: FlowOutput,
+        Flow<F, U, CHAN_N, Q, ST, H, JOURNAL_N>: Send,
+        ArcSlot<U, UD, FD, CHAN_N, DATA_N, Q, ST, H, JOURNAL_N>: Send,
+        UserDataHandle<UD, FD, DATA_N>: Send,
+        U: 'static,
+        UD: 'static,
+        FD: 'static,
+        Q: 'static,
+        ST: State,
+        H: Handler<ST, FlowEvent<U>, Output = Reply<U>> + Default + 'static,
+    {
+        self.supervised(restart, function, init, slot, Some(Box::new(on_run)))
+    }
+
+    #[allow(clippy::type_complexity)]
+    fn supervised<
+        Fun,
+        I,
+        F,
+        U,
+        UD,
+        FD,
+        const CHAN_N: usize,
+        const DATA_N: usize,
+        Q,
+        ST,
+        H,
+        const JOURNAL_N: usize,
+    >(
+        self,
+        restart: Restart,
+        function: Fun,
+        init: I,
+        slot: ArcSlot<U, UD, FD, CHAN_N, DATA_N, Q, ST, H, JOURNAL_N>,
+        on_run: Option<OnRun<UD, FD, DATA_N>>,
+    ) -> Self
+    where
+        Fun: Fn(
+                I,
+                FnController<R, U, CHAN_N, Q, ST, H, JOURNAL_N>,
+                FnDataHandle<UD, FD, DATA_N>,
+            ) -> F
+            + Send
+            + 'static,
+        I: Clone + Send + 'static,
+        F: Future + 'static,
+        F::Output: FlowOutput,
+        Flow<F, U, CHAN_N, Q, ST, H, JOURNAL_N>: Send,
+        ArcSlot<U, UD, FD, CHAN_N, DATA_N, Q, ST, H, JOURNAL_N>: Send,
+        UserDataHandle<UD, FD, DATA_N>: Send,
+        U: 'static,
+        UD: 'static,
+        FD: 'static,
+        Q: 'static,
+        ST: State,
+        H: Handler<ST, FlowEvent<U>, Output = Reply<U>> + Default + 'static,
+    {
+        let child = Supervised {
+            runtime: self.runtime.clone(),
+            function,
+            init,
+            slot,
+            restart,
+            run: None,
+            data: None,
+            on_run,
+            used: false,
+        };
+        self.with_child(child)
+    }
+
+    /// used when a child ended, decides whether and how it is restarted
+    fn ended(&mut self, index: usize, state: &FlowState) {
+        if self.stopping.is_some() || !self.children[index].0.restart().applies(state) {
+            return;
+        }
+        let now = self.runtime.now_us();
+        let window = self.policy.window_ms as u64 * 1000;
+        while self
+            .restarts
+            .front()
+            .is_some_and(|&at| now.saturating_sub(at) > window)
+        {
+            self.restarts.pop_front();
+        }
+        if self.restarts.len() >= self.policy.max_restarts as usize {
+            self.stop_all(Stop::GiveUp);
+            return;
+        }
+        self.restarts.push_back(now);
+        let backoff = self.policy.backoff(self.restarts.len() as u32);
+        match self.strategy {
+            Strategy::OneForOne => {
+                self.children[index].1 = Status::Waiting(Box::pin(self.runtime.delay_ms(backoff)))
+            }
+            Strategy::OneForAll => {
+                self.children[index].1 = Status::Stopped;
+                self.stop_all(Stop::Restart(backoff));
+            }
+        }
+    }
+
+    /// used by the poll to move a child along until it waits on something
+    fn drive(&mut self, index: usize, cx: &mut Context<'_>) {
+        loop {
+            let (child, status) = &mut self.children[index];
+            match status {
+                Status::Waiting(delay) => match delay.as_mut().poll(cx) {
+                    Poll::Ready(()) => *status = Status::Idle,
+                    Poll::Pending => return,
+                },
+                Status::Idle => {
+                    child.start();
+                    *status = Status::Running;
+                }
+                Status::Running => match child.poll_run(cx) {
+                    Poll::Ready(state) => {
+                        *status = Status::Ended;
+                        self.ended(index, &state);
+                    }
+                    Poll::Pending => return,
+                },
+                Status::Stopping => match child.poll_run(cx) {
+                    Poll::Ready(_) => *status = Status::Stopped,
+                    Poll::Pending => return,
+                },
+                Status::Stopped | Status::Ended => return,
+            }
+        }
+    }
+
+    fn stop_all(&mut self, stop: Stop) {
+        self.stopping = Some(stop);
+        for (child, status) in &mut self.children {
+            match status {
+                Status::Running => {
+                    child.stop();
+                    *status = Status::Stopping;
+                }
+                Status::Idle | Status::Waiting(_) => *status = Status::Stopped,
+                Status::Stopping | Status::Stopped | Status::Ended => {}
+            }
+        }
+    }
+
+    /// used once no child runs anymore, `None` if the supervisor goes on
+    fn stopped(&mut self) -> Option<FlowState> {
+        match self.stopping.take()? {
+            Stop::Restart(backoff) => {
+                for (child, status) in &mut self.children {
+                    if matches!(status, Status::Stopped) && child.restart() != Restart::Never {
+                        *status = Status::Waiting(Box::pin(self.runtime.delay_ms(backoff)));
+                    }
+                }
+                None
+            }
+            Stop::GiveUp => Some(FlowState::Error),
+            Stop::Cancel => Some(FlowState::Cancelled),
+        }
+    }
+}
+
+impl<R: FlowRuntime> Child for Supervisor<R>
+where
+    R::DelayFuture: Send,
+{
+    fn start(&mut self) {
+        for (_, status) in &mut self.children {
+            *status = Status::Idle;
+        }
+        self.restarts.clear();
+        self.stopping = None;
+        // a stop of the previous run is done with
+        self.stop.inner.requested.store(false, Ordering::Release);
+    }
+
+    fn poll_run(&mut self, cx: &mut Context<'_>) -> Poll<FlowState> {
+        self.stop.inner.waker.register(cx.waker());
+        if self.stop.inner.requested.swap(false, Ordering::AcqRel)
+            && self.stopping != Some(Stop::GiveUp)
+        {
+            self.stop_all(Stop::Cancel);
+        }
+        loop {
+            for index in 0..self.children.len() {
+                self.drive(index, cx);
+            }
+            let running = self
+                .children
+                .iter()
+                .any(|(_, status)| matches!(status, Status::Running | Status::Stopping));
+            if running {
+                return Poll::Pending;
+            }
+            if self.stopping.is_some() {
+                match self.stopped() {
+                    Some(state) => return Poll::Ready(state),
+                    // the delays of the restart have to be polled once to be woken
+                    None => continue,
+                }
+            }
+            let waiting = self
+                .children
+                .iter()
+                .any(|(_, status)| matches!(status, Status::Waiting(_)));
+            if waiting {
+                return Poll::Pending;
+            }
+            return Poll::Ready(FlowState::Completed);
+        }
+    }
+
+    fn stop(&mut self) {
+        self.stop_all(Stop::Cancel);
+    }
+
+    fn restart(&self) -> Restart {
+        self.restart
+    }
+}
+
+impl<R: FlowRuntime> Future for Supervisor<R>
+where
+    R::DelayFuture: Send,
+{
+    type Output = FlowState;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<FlowState> {
+        self.get_mut().poll_run(cx)
+    }
+}
@@ -0,0 +1,48 @@
+//! Observability hooks, compiled out unless the `tracing` (std) or `defmt` (embedded) feature is on
+
+/// a flow moved between states while consuming an event
+macro_rules! transition {
+    ($from:expr, $to:expr, $event:expr) => {{
+        #[cfg(feature = "tracing")]
+        tracing::debug!(from = ?$from, to = ?$to, event = $event, "flow transition");
+        #[cfg(feature = "defmt")]
+        defmt::debug!(
+            "flow transition {} -> {} on {=str}",
+            defmt::Debug2Format(&$from),
+            defmt::Debug2Format(&$to),
+            $event
+        );
+        #[cfg(not(any(feature = "tracing", feature = "defmt")))]
+        let _ = (&$from, &$to, $event);
+    }};
+}
+
+/// a flow future was polled and settled in a state
+macro_rules! poll {
+    ($state:expr, $ready:expr) => {{
+        #[cfg(feature = "tracing")]
+        tracing::trace!(state = ?$state, ready = $ready, "flow poll");
+        #[cfg(feature = "defmt")]
+        defmt::trace!(
+            "flow poll in {}, ready: {=bool}",
+            defmt::Debug2Format(&$state),
+            $ready
+        );
+        #[cfg(not(any(feature = "tracing", feature = "defmt")))]
+        let _ = (&$state, $ready);
+    }};
+}
+
+/// data was pushed to one direction of a data channel
+macro_rules! push {
+    ($direction:expr, $outcome:expr) => {{
+        #[cfg(feature = "tracing")]
+        tracing::trace!(direction = $direction, outcome = $outcome, "data push");
+        #[cfg(feature = "defmt")]
+        defmt::trace!("data push to {=str}: {=str}", $direction, $outcome);
+        #[cfg(not(any(feature = "tracing", feature = "defmt")))]
+        let _ = ($direction, $outcome);
+    }};
+}
+
+pub(crate) use {poll, push, transition};
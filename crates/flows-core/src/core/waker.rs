@@ -1 +1,72 @@
+use super::lock::SpinLock;
+use core::task::Waker;
 pub use futures_core::task::__internal::AtomicWaker;
+use portable_atomic::{AtomicU32, Ordering};
+
+/// How many tasks a `WakerSet` holds at once without `alloc`
+pub const WAITERS: usize = 8;
+
+#[cfg(feature = "alloc")]
+type Wakers = alloc::vec::Vec<(u32, Waker)>;
+#[cfg(not(feature = "alloc"))]
+type Wakers = heapless::Vec<(u32, Waker), WAITERS>;
+
+/// Wakers of every task waiting on the same thing, e.g. the observers of a flow's state
+/// Each waiter registers under its own id, so polling again replaces its waker instead of another one's.
+/// Without `alloc` it holds up to `WAITERS` wakers, a further waiter wakes the oldest one to make room.
+pub struct WakerSet {
+    next_id: AtomicU32,
+    wakers: SpinLock<Wakers>,
+}
+
+impl Default for WakerSet {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl WakerSet {
+    pub const fn new() -> Self {
+        WakerSet {
+            next_id: AtomicU32::new(0),
+            wakers: SpinLock::new(Wakers::new()),
+        }
+    }
+
+    /// A new waiter id to register under
+    pub fn id(&self) -> u32 {
+        self.next_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Wake `waker` on the next `wake`
+    pub fn register(&self, id: u32, waker: &Waker) {
+        let mut wakers = self.wakers.lock();
+        if let Some((_, registered)) = wakers.iter_mut().find(|(at, _)| *at == id) {
+            registered.clone_from(waker);
+            return;
+        }
+        #[cfg(feature = "alloc")]
+        wakers.push((id, waker.clone()));
+        #[cfg(not(feature = "alloc"))]
+        {
+            if wakers.is_full() {
+                // the oldest waiter checks again and registers anew
+                wakers.remove(0).1.wake();
+            }
+            let _ = wakers.push((id, waker.clone()));
+        }
+    }
+
+    /// used by a waiter that is dropped before it was woken
+    pub fn remove(&self, id: u32) {
+        self.wakers.lock().retain(|(at, _)| *at != id);
+    }
+
+    /// Wake every registered waiter, they register again if they keep waiting
+    pub fn wake(&self) {
+        let wakers = core::mem::take(&mut *self.wakers.lock());
+        for (_, waker) in wakers {
+            waker.wake();
+        }
+    }
+}
@@ -1,17 +1,215 @@
-use super::{FlowRuntime, Spawner, Timer, WakerBuilder};
+use super::{Clock, FlowRuntime, LocalSpawner, Spawner, Timer, WakerBuilder};
+use crate::core::AtomicWaker;
+use core::fmt;
 use core::future::Future;
-use core::task::Waker;
+use core::mem::MaybeUninit;
+use core::pin::Pin;
+use core::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+use embassy_executor::raw::{AvailableTask, TaskStorage};
+use embassy_executor::{SendSpawner, SpawnToken};
 
-#[derive(Debug, Clone)]
-pub struct EmbassyRuntime;
+/// Runs flows on an embassy executor
+/// Flows are spawned into the task storage of a static `FlowTasks`, one flow per storage,
+/// and each flow future has to fit in `SIZE` bytes
+#[derive(Clone)]
+pub struct EmbassyRuntime<const SIZE: usize = 1024> {
+    spawner: SendSpawner,
+    tasks: &'static [TaskStorage<FlowTask<SIZE>>],
+    wakers: &'static [AtomicWaker],
+    /// wakes every task spawned into `tasks`
+    waker: Waker,
+}
+
+impl<const SIZE: usize> EmbassyRuntime<SIZE> {
+    /// Spawn flows with `spawner` into `tasks`
+    /// Use `Spawner::make_send` on the spawner handed to `main`, or `SendSpawner::for_current_executor`
+    pub fn new<const N: usize>(spawner: SendSpawner, tasks: &'static FlowTasks<N, SIZE>) -> Self {
+        Self {
+            spawner,
+            tasks: &tasks.tasks,
+            wakers: &tasks.wakers,
+            waker: tasks.waker(),
+        }
+    }
+
+    /// Spawns flows that are not `Send` on the executor of `spawner`, into the same task storage
+    pub fn local(&self, spawner: embassy_executor::Spawner) -> EmbassyLocalSpawner<SIZE> {
+        EmbassyLocalSpawner {
+            spawner,
+            tasks: self.tasks,
+            wakers: self.wakers,
+        }
+    }
+}
+
+/// Spawns flows that are not `Send`, see `EmbassyRuntime::local`
+/// It is bound to its executor, so has to be used from there
+#[derive(Clone)]
+pub struct EmbassyLocalSpawner<const SIZE: usize = 1024> {
+    spawner: embassy_executor::Spawner,
+    tasks: &'static [TaskStorage<FlowTask<SIZE>>],
+    wakers: &'static [AtomicWaker],
+}
+
+/// used by the spawners to put `future` into the first free task storage
+fn prepare<F, const SIZE: usize>(
+    tasks: &'static [TaskStorage<FlowTask<SIZE>>],
+    wakers: &'static [AtomicWaker],
+    future: F,
+) -> Result<SpawnToken<FlowTask<SIZE>>, SpawnError>
+where
+    F: Future<Output = ()> + 'static,
+{
+    if !FlowTask::<SIZE>::fits::<F>() {
+        return Err(SpawnError::TooLarge);
+    }
+    let (task, waker) = tasks
+        .iter()
+        .zip(wakers)
+        .find_map(|(task, waker)| AvailableTask::claim(task).map(|task| (task, waker)))
+        .ok_or(SpawnError::Busy)?;
+    Ok(task.initialize(|| FlowTask::new(future, waker)))
+}
+
+impl<const SIZE: usize> fmt::Debug for EmbassyRuntime<SIZE> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EmbassyRuntime")
+            .field("tasks", &self.tasks.len())
+            .field("size", &SIZE)
+            .finish()
+    }
+}
+
+/// Why a flow could not be spawned on embassy
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SpawnError {
+    /// every task storage is taken by a flow that is still running
+    Busy,
+    /// the flow future is larger than a task storage, or more strictly aligned
+    TooLarge,
+}
+
+impl fmt::Display for SpawnError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SpawnError::Busy => write!(f, "every flow task storage is in use"),
+            SpawnError::TooLarge => write!(f, "the flow does not fit in a task storage"),
+        }
+    }
+}
+
+impl core::error::Error for SpawnError {}
+
+/// Static task storage for `N` flows of up to `SIZE` bytes each
+///
+/// ```ignore
+/// static TASKS: FlowTasks<4> = FlowTasks::new();
+/// let runtime = EmbassyRuntime::new(spawner.make_send(), &TASKS);
+/// ```
+pub struct FlowTasks<const N: usize, const SIZE: usize = 1024> {
+    tasks: [TaskStorage<FlowTask<SIZE>>; N],
+    /// the waker each task was last polled with
+    wakers: [AtomicWaker; N],
+}
+
+impl<const N: usize, const SIZE: usize> Default for FlowTasks<N, SIZE> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize, const SIZE: usize> FlowTasks<N, SIZE> {
+    pub const fn new() -> Self {
+        Self {
+            tasks: [const { TaskStorage::new() }; N],
+            wakers: [const { AtomicWaker::new() }; N],
+        }
+    }
+
+    fn waker(&'static self) -> Waker {
+        let data = (self as *const Self).cast::<()>();
+        // the tasks live forever, the waker has nothing to release
+        unsafe { Waker::from_raw(RawWaker::new(data, Self::vtable())) }
+    }
+
+    fn vtable() -> &'static RawWakerVTable {
+        const {
+            &RawWakerVTable::new(
+                |data| RawWaker::new(data, Self::vtable()),
+                Self::wake_all,
+                Self::wake_all,
+                |_| {},
+            )
+        }
+    }
+
+    unsafe fn wake_all(data: *const ()) {
+        let tasks = unsafe { &*data.cast::<Self>() };
+        tasks.wakers.iter().for_each(AtomicWaker::wake);
+    }
+}
+
+#[repr(C, align(8))]
+struct Buffer<const SIZE: usize>([MaybeUninit<u8>; SIZE]);
+
+/// A spawned flow future with its type erased, stored inline
+struct FlowTask<const SIZE: usize> {
+    buffer: Buffer<SIZE>,
+    poll: unsafe fn(*mut u8, &mut Context<'_>) -> Poll<()>,
+    drop: unsafe fn(*mut u8),
+    waker: &'static AtomicWaker,
+}
+
+// futures that are not `Send` only go to the executor of an `EmbassyLocalSpawner`,
+// which runs them on the thread that spawned them
+unsafe impl<const SIZE: usize> Send for FlowTask<SIZE> {}
+
+impl<const SIZE: usize> FlowTask<SIZE> {
+    /// used by the runtime once it checked that `F` fits in the buffer
+    fn new<F: Future<Output = ()>>(future: F, waker: &'static AtomicWaker) -> Self {
+        assert!(Self::fits::<F>());
+        let mut buffer = Buffer([MaybeUninit::uninit(); SIZE]);
+        unsafe { buffer.0.as_mut_ptr().cast::<F>().write(future) };
+        Self {
+            buffer,
+            poll: poll_erased::<F>,
+            drop: drop_erased::<F>,
+            waker,
+        }
+    }
+
+    fn fits<F>() -> bool {
+        size_of::<F>() <= SIZE && align_of::<F>() <= align_of::<Buffer<SIZE>>()
+    }
+}
+
+unsafe fn poll_erased<F: Future<Output = ()>>(future: *mut u8, cx: &mut Context<'_>) -> Poll<()> {
+    unsafe { Pin::new_unchecked(&mut *future.cast::<F>()) }.poll(cx)
+}
+
+unsafe fn drop_erased<F>(future: *mut u8) {
+    unsafe { future.cast::<F>().drop_in_place() }
+}
+
+impl<const SIZE: usize> Future for FlowTask<SIZE> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        // the task storage never moves the task, neither is the future moved out of the buffer
+        let this = unsafe { self.get_unchecked_mut() };
+        this.waker.register(cx.waker());
+        unsafe { (this.poll)(this.buffer.0.as_mut_ptr().cast(), cx) }
+    }
+}
 
-impl EmbassyRuntime {
-    pub fn new() -> Self {
-        Self
+impl<const SIZE: usize> Drop for FlowTask<SIZE> {
+    fn drop(&mut self) {
+        unsafe { (self.drop)(self.buffer.0.as_mut_ptr().cast()) }
     }
 }
 
-impl Timer for EmbassyRuntime {
+impl<const SIZE: usize> Timer for EmbassyRuntime<SIZE> {
     type DelayFuture = embassy_time::Timer;
 
     fn delay_ms(&self, millis: u32) -> Self::DelayFuture {
@@ -23,46 +221,52 @@ impl Timer for EmbassyRuntime {
     }
 }
 
+impl<const SIZE: usize> Clock for EmbassyRuntime<SIZE> {
+    /// Time since boot
+    fn now_us(&self) -> u64 {
+        embassy_time::Instant::now().as_micros()
+    }
 
-impl Spawner for EmbassyRuntime {
+    fn now_fn(&self) -> Option<fn() -> u64> {
+        Some(|| embassy_time::Instant::now().as_micros())
+    }
+}
+
+impl<const SIZE: usize> Spawner for EmbassyRuntime<SIZE> {
     type Handle = ();
-    type Error = ();
+    type Error = SpawnError;
 
-    fn spawn<F>(&self, _future: F) -> Result<Self::Handle, Self::Error>
+    fn spawn<F>(&self, future: F) -> Result<Self::Handle, Self::Error>
     where
         F: Future<Output = ()> + Send + 'static,
     {
-        // Embassy spawning would be runtime-specific and typically handled
-        // by the executor. This is a placeholder implementation.
-        Ok(())
+        let token = prepare(self.tasks, self.wakers, future)?;
+        self.spawner.spawn(token).map_err(|_| SpawnError::Busy)
     }
 }
 
-impl WakerBuilder for EmbassyRuntime {
-    fn build_waker(&self) -> Waker {
-        // For embassy, we create a simple waker
-        // In practice, this would be integrated with the embassy executor
-        use core::task::{RawWaker, RawWakerVTable};
-        
-        const VTABLE: RawWakerVTable =
-            RawWakerVTable::new(|_| RAW_WAKER, |_| {}, |_| {}, |_| {});
-        const RAW_WAKER: RawWaker = RawWaker::new(core::ptr::null(), &VTABLE);
-        
-        unsafe { Waker::from_raw(RAW_WAKER) }
+impl<const SIZE: usize> LocalSpawner for EmbassyLocalSpawner<SIZE> {
+    type Handle = ();
+    type Error = SpawnError;
+
+    fn spawn_local<F>(&self, future: F) -> Result<Self::Handle, Self::Error>
+    where
+        F: Future<Output = ()> + 'static,
+    {
+        let token = prepare(self.tasks, self.wakers, future)?;
+        self.spawner.spawn(token).map_err(|_| SpawnError::Busy)
     }
 }
 
-impl FlowRuntime for EmbassyRuntime {
-    async fn yield_now(&self) {
-        // Embassy equivalent of yielding
-        // embassy_executor::yield_now().await;
-        core::future::ready(()).await;
+impl<const SIZE: usize> WakerBuilder for EmbassyRuntime<SIZE> {
+    /// Wakes every flow spawned through this runtime
+    fn build_waker(&self) -> Waker {
+        self.waker.clone()
     }
 }
 
-
-impl Default for EmbassyRuntime {
-    fn default() -> Self {
-        Self::new()
+impl<const SIZE: usize> FlowRuntime for EmbassyRuntime<SIZE> {
+    async fn yield_now(&self) {
+        embassy_futures::yield_now().await
     }
 }
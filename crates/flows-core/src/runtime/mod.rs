@@ -1,3 +1,11 @@
+use crate::core::{
+    Flow, FlowEvent, FlowOutput, FnController, FnDataHandle, Handler, JoinCell, LaunchError,
+    Launched, OutcomeOf, Reply, Reset, Slot, State, flow::name_of, launch::Deliver,
+};
+#[cfg(feature = "alloc")]
+use crate::core::{FlowHandle, FlowSlot};
+#[cfg(feature = "alloc")]
+use alloc::sync::Arc;
 use core::future::Future;
 use core::task::Waker;
 
@@ -7,12 +15,33 @@ pub mod tokio;
 #[cfg(feature = "embassy")]
 pub mod embassy;
 
+#[cfg(feature = "sim")]
+pub mod sim;
+
+#[cfg(feature = "std")]
+pub mod thread;
+
+#[cfg(feature = "smol")]
+pub mod smol;
+
 pub trait Timer {
     type DelayFuture: Future<Output = ()>;
     fn delay_ms(&self, millis: u32) -> Self::DelayFuture;
     fn delay_us(&self, micros: u64) -> Self::DelayFuture;
 }
 
+/// Source of timestamps, e.g. for the journal of a flow
+pub trait Clock {
+    /// Microseconds on the runtime's clock, only comparable with other readings of the same clock
+    fn now_us(&self) -> u64;
+
+    /// The same clock as a plain function, for journals that keep it without a heap
+    /// `None` if reading it takes the runtime itself
+    fn now_fn(&self) -> Option<fn() -> u64> {
+        None
+    }
+}
+
 pub trait Spawner {
     type Handle;
     type Error;
@@ -22,10 +51,310 @@ pub trait Spawner {
         F: Future<Output = ()> + Send + 'static;
 }
 
+/// Spawns futures that stay on the thread or executor they were spawned from, so need not be `Send`
+pub trait LocalSpawner {
+    type Handle;
+    type Error;
+
+    fn spawn_local<F>(&self, future: F) -> Result<Self::Handle, Self::Error>
+    where
+        F: Future<Output = ()> + 'static;
+}
+
 pub trait WakerBuilder {
     fn build_waker(&self) -> Waker;
 }
 
-pub trait FlowRuntime: Timer + Spawner + WakerBuilder + Clone + Send + Sync + 'static {
+pub trait FlowRuntime:
+    Timer + Clock + Spawner + WakerBuilder + Clone + Send + Sync + 'static
+{
     fn yield_now(&self) -> impl Future<Output = ()>;
+
+    /// Run `function` with `init` as a flow in `slot` on this runtime
+    /// `slot` has to be new or reset, e.g. an `ArcSlot::new()` or a slot from `SlotPool::acquire`
+    #[cfg(feature = "alloc")]
+    #[allow(clippy::type_complexity)]
+    fn spawn_flow<
+        Fun,
+        I,
+        F,
+        S,
+        U,
+        UD,
+        FD,
+        const CHAN_N: usize,
+        const DATA_N: usize,
+        Q,
+        ST,
+        H,
+        const JOURNAL_N: usize,
+    >(
+        &self,
+        function: Fun,
+        init: I,
+        slot: S,
+    ) -> Result<
+        FlowHandle<
+            OutcomeOf<F::Output>,
+            U,
+            UD,
+            FD,
+            CHAN_N,
+            DATA_N,
+            Q,
+            ST,
+            H,
+            Self::Handle,
+            JOURNAL_N,
+        >,
+        Self::Error,
+    >
+    where
+        Fun: FnOnce(
+            I,
+            FnController<Self, U, CHAN_N, Q, ST, H, JOURNAL_N>,
+            FnDataHandle<UD, FD, DATA_N>,
+        ) -> F,
+        F: Future,
+        F::Output: FlowOutput,
+        OutcomeOf<F::Output>: Send + 'static,
+        Flow<F, U, CHAN_N, Q, ST, H, JOURNAL_N>: Send + 'static,
+        S: FlowSlot<U, UD, FD, CHAN_N, DATA_N, Q, ST, H, JOURNAL_N>,
+        U: 'static,
+        UD: 'static,
+        FD: 'static,
+        Q: 'static,
+        ST: State,
+        H: Handler<ST, FlowEvent<U>, Output = Reply<U>> + 'static,
+    {
+        let ((fn_ctrl, flow_ctrl, user_ctrl), (fn_data, user_data)) = slot.parts(self);
+        let flow =
+            Flow::new(function(init, fn_ctrl, fn_data), flow_ctrl).with_name(name_of::<Fun>());
+        let join = Arc::new(JoinCell::new());
+        join.claim();
+        let task = self.spawn(Deliver::new(join.clone(), flow))?;
+        Ok(FlowHandle::new(user_ctrl, user_data, join, task))
+    }
+
+    /// Like `spawn_flow`, for functions whose futures are not `Send`
+    /// The flow runs on `spawner`, the function still waits and yields through this runtime
+    #[cfg(feature = "alloc")]
+    #[allow(clippy::type_complexity)]
+    fn spawn_local_flow<
+        L,
+        Fun,
+        I,
+        F,
+        S,
+        U,
+        UD,
+        FD,
+        const CHAN_N: usize,
+        const DATA_N: usize,
+        Q,
+        ST,
+        H,
+        const JOURNAL_N: usize,
+    >(
+        &self,
+        spawner: &L,
+        function: Fun,
+        init: I,
+        slot: S,
+    ) -> Result<
+        FlowHandle<OutcomeOf<F::Output>, U, UD, FD, CHAN_N, DATA_N, Q, ST, H, L::Handle, JOURNAL_N>,
+        L::Error,
+    >
+    where
+        L: LocalSpawner,
+        Fun: FnOnce(
+            I,
+            FnController<Self, U, CHAN_N, Q, ST, H, JOURNAL_N>,
+            FnDataHandle<UD, FD, DATA_N>,
+        ) -> F,
+        F: Future + 'static,
+        F::Output: FlowOutput,
+        OutcomeOf<F::Output>: 'static,
+        S: FlowSlot<U, UD, FD, CHAN_N, DATA_N, Q, ST, H, JOURNAL_N>,
+        U: 'static,
+        UD: 'static,
+        FD: 'static,
+        Q: 'static,
+        ST: State,
+        H: Handler<ST, FlowEvent<U>, Output = Reply<U>> + 'static,
+    {
+        let ((fn_ctrl, flow_ctrl, user_ctrl), (fn_data, user_data)) = slot.parts(self);
+        let flow =
+            Flow::new(function(init, fn_ctrl, fn_data), flow_ctrl).with_name(name_of::<Fun>());
+        let join = Arc::new(JoinCell::new());
+        join.claim();
+        let task = spawner.spawn_local(Deliver::new(join.clone(), flow))?;
+        Ok(FlowHandle::new(user_ctrl, user_data, join, task))
+    }
+
+    /// Run `function` with `init` as a flow in the static `slot`, for targets without a heap
+    /// The outcome is handed over through `join`, one run at a time: while the previous run in
+    /// `join` has not ended this fails with `LaunchError::Busy`, otherwise `slot` is reset first.
+    ///
+    /// ```ignore
+    /// static SLOT: Slot<(), (), u32, 4, 8> = Slot::new();
+    /// static JOIN: JoinCell<FlowOutcome<(), ()>> = JoinCell::new();
+    /// let flow = runtime.launch_flow(counter, 10, &SLOT, &JOIN)?;
+    /// ```
+    #[allow(clippy::type_complexity)]
+    fn launch_flow<
+        Fun,
+        I,
+        F,
+        U,
+        UD,
+        FD,
+        const CHAN_N: usize,
+        const DATA_N: usize,
+        Q,
+        ST,
+        H,
+        const JOURNAL_N: usize,
+    >(
+        &self,
+        function: Fun,
+        init: I,
+        slot: &'static Slot<U, UD, FD, CHAN_N, DATA_N, Q, ST, H, JOURNAL_N>,
+        join: &'static JoinCell<OutcomeOf<F::Output>>,
+    ) -> Result<
+        Launched<
+            OutcomeOf<F::Output>,
+            U,
+            UD,
+            FD,
+            CHAN_N,
+            DATA_N,
+            Q,
+            ST,
+            H,
+            Self::Handle,
+            JOURNAL_N,
+        >,
+        LaunchError<Self::Error>,
+    >
+    where
+        Fun: FnOnce(
+            I,
+            FnController<Self, U, CHAN_N, Q, ST, H, JOURNAL_N>,
+            FnDataHandle<UD, FD, DATA_N>,
+        ) -> F,
+        F: Future,
+        F::Output: FlowOutput,
+        OutcomeOf<F::Output>: Send + 'static,
+        Flow<F, U, CHAN_N, Q, ST, H, JOURNAL_N>: Send + 'static,
+        U: 'static,
+        UD: 'static,
+        FD: 'static,
+        Q: 'static,
+        ST: State,
+        H: Handler<ST, FlowEvent<U>, Output = Reply<U>> + 'static,
+    {
+        if !join.claim() {
+            return Err(LaunchError::Busy);
+        }
+        slot.reset();
+        let (fn_data, user_data) = slot.handles();
+        let (fn_ctrl, flow_ctrl, user_ctrl) = slot.ctrls(self);
+        let flow =
+            Flow::new(function(init, fn_ctrl, fn_data), flow_ctrl).with_name(name_of::<Fun>());
+        match self.spawn(Deliver::new(join, flow)) {
+            Ok(task) => Ok(Launched {
+                ctrl: user_ctrl,
+                data: user_data,
+                join: join.join(),
+                task,
+            }),
+            Err(e) => {
+                join.release();
+                Err(LaunchError::Spawn(e))
+            }
+        }
+    }
+
+    /// Like `launch_flow`, for functions whose futures are not `Send`
+    /// The flow runs on `spawner`, the function still waits and yields through this runtime
+    #[allow(clippy::type_complexity)]
+    fn launch_local_flow<
+        L,
+        Fun,
+        I,
+        F,
+        U,
+        UD,
+        FD,
+        const CHAN_N: usize,
+        const DATA_N: usize,
+        Q,
+        ST,
+        H,
+        const JOURNAL_N: usize,
+    >(
+        &self,
+        spawner: &L,
+        function: Fun,
+        init: I,
+        slot: &'static Slot<U, UD, FD, CHAN_N, DATA_N, Q, ST, H, JOURNAL_N>,
+        join: &'static JoinCell<OutcomeOf<F::Output>>,
+    ) -> Result<
+        Launched<OutcomeOf<F::Output>, U, UD, FD, CHAN_N, DATA_N, Q, ST, H, L::Handle, JOURNAL_N>,
+        LaunchError<L::Error>,
+    >
+    where
+        L: LocalSpawner,
+        Fun: FnOnce(
+            I,
+            FnController<Self, U, CHAN_N, Q, ST, H, JOURNAL_N>,
+            FnDataHandle<UD, FD, DATA_N>,
+        ) -> F,
+        F: Future + 'static,
+        F::Output: FlowOutput,
+        OutcomeOf<F::Output>: 'static,
+        U: 'static,
+        UD: 'static,
+        FD: 'static,
+        Q: 'static,
+        ST: State,
+        H: Handler<ST, FlowEvent<U>, Output = Reply<U>> + 'static,
+    {
+        if !join.claim() {
+            return Err(LaunchError::Busy);
+        }
+        slot.reset();
+        let (fn_data, user_data) = slot.handles();
+        let (fn_ctrl, flow_ctrl, user_ctrl) = slot.ctrls(self);
+        let flow =
+            Flow::new(function(init, fn_ctrl, fn_data), flow_ctrl).with_name(name_of::<Fun>());
+        match spawner.spawn_local(Deliver::new(join, flow)) {
+            Ok(task) => Ok(Launched {
+                ctrl: user_ctrl,
+                data: user_data,
+                join: join.join(),
+                task,
+            }),
+            Err(e) => {
+                join.release();
+                Err(LaunchError::Spawn(e))
+            }
+        }
+    }
+}
+
+/// used by runtimes without a yield of their own, wakes the task and lets the others run first
+#[cfg(feature = "std")]
+pub(crate) async fn yield_once() {
+    let mut yielded = false;
+    core::future::poll_fn(|cx| {
+        if yielded {
+            return core::task::Poll::Ready(());
+        }
+        yielded = true;
+        cx.waker().wake_by_ref();
+        core::task::Poll::Pending
+    })
+    .await
 }
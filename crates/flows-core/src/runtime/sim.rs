@@ -0,0 +1,423 @@
+use super::{Clock, FlowRuntime, Spawner, Timer, WakerBuilder};
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll, Waker};
+use portable_atomic::{AtomicBool, AtomicU64, Ordering};
+use std::boxed::Box;
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::task::Wake;
+
+type Task = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// Runtime for tests: time only moves when the test advances it,
+/// and spawned futures are polled one at a time on the calling thread in the order they were woken
+/// Running the same scenario twice polls the same futures in the same order at the same virtual times
+#[derive(Clone, Default)]
+pub struct SimRuntime {
+    inner: Arc<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    /// virtual time in microseconds
+    now: AtomicU64,
+    next_id: AtomicU64,
+    /// spawned futures by id, taken out while they are polled
+    tasks: Mutex<BTreeMap<u64, SimTask>>,
+    ready: Arc<Mutex<VecDeque<u64>>>,
+    /// wakers of pending delays by deadline, in the order they were registered
+    timers: Mutex<BTreeMap<(u64, u64), Waker>>,
+}
+
+struct SimTask {
+    future: Option<Task>,
+    /// the one waker of the task, handed to every poll
+    waker: Arc<TaskWaker>,
+}
+
+struct TaskWaker {
+    id: u64,
+    /// set while the task sits in the ready queue, so it is queued once however often it is woken
+    queued: AtomicBool,
+    ready: Arc<Mutex<VecDeque<u64>>>,
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref()
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        if !self.queued.swap(true, Ordering::AcqRel) {
+            self.ready.lock().unwrap().push_back(self.id);
+        }
+    }
+}
+
+impl core::fmt::Debug for SimRuntime {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("SimRuntime")
+            .field("now_us", &self.time_us())
+            .field("tasks", &self.tasks())
+            .finish()
+    }
+}
+
+impl SimRuntime {
+    /// A runtime at virtual time 0
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Virtual time in microseconds
+    pub fn time_us(&self) -> u64 {
+        self.inner.now.load(Ordering::Acquire)
+    }
+
+    /// Spawned futures that have not finished yet
+    pub fn tasks(&self) -> usize {
+        self.inner.tasks.lock().unwrap().len()
+    }
+
+    /// Deadline of the earliest pending delay, in microseconds of virtual time
+    pub fn next_deadline_us(&self) -> Option<u64> {
+        self.inner
+            .timers
+            .lock()
+            .unwrap()
+            .keys()
+            .next()
+            .map(|&(deadline, _)| deadline)
+    }
+
+    /// Poll the woken futures until none is left, without moving time
+    /// Returns how many polls it took
+    pub fn run_until_stalled(&self) -> usize {
+        let mut polls = 0;
+        loop {
+            let Some(id) = self.inner.ready.lock().unwrap().pop_front() else {
+                return polls;
+            };
+            // taken out so the future can spawn while it is polled
+            let Some((mut future, waker)) =
+                self.inner
+                    .tasks
+                    .lock()
+                    .unwrap()
+                    .get_mut(&id)
+                    .and_then(|task| {
+                        let future = task.future.take()?;
+                        Some((future, task.waker.clone()))
+                    })
+            else {
+                continue;
+            };
+            // a wake during the poll queues the task again
+            waker.queued.store(false, Ordering::Release);
+            polls += 1;
+            let done = future
+                .as_mut()
+                .poll(&mut Context::from_waker(&Waker::from(waker)))
+                .is_ready();
+            let mut tasks = self.inner.tasks.lock().unwrap();
+            if done {
+                tasks.remove(&id);
+            } else if let Some(task) = tasks.get_mut(&id) {
+                task.future = Some(future);
+            }
+        }
+    }
+
+    /// Move time forward by `micros`, firing the delays due on the way in deadline order
+    pub fn advance_us(&self, micros: u64) {
+        let until = self.time_us().saturating_add(micros);
+        self.run_until_stalled();
+        while self.fire_next(until) {
+            self.run_until_stalled();
+        }
+        self.inner.now.store(until, Ordering::Release);
+        self.run_until_stalled();
+    }
+
+    pub fn advance_ms(&self, millis: u32) {
+        self.advance_us(millis as u64 * 1000)
+    }
+
+    /// Run until every spawned future is either done or waits on something other than time,
+    /// jumping straight to the next deadline whenever nothing else is left to poll
+    pub fn run(&self) {
+        self.run_until_stalled();
+        while self.fire_next(u64::MAX) {
+            self.run_until_stalled();
+        }
+    }
+
+    /// Drive `future` on the calling thread together with the spawned futures,
+    /// jumping to the next deadline whenever nothing else is left to poll
+    /// Panics if `future` is still pending once nothing can wake it anymore
+    pub fn block_on<F: Future>(&self, future: F) -> F::Output {
+        struct Flag(AtomicBool);
+
+        impl Wake for Flag {
+            fn wake(self: Arc<Self>) {
+                self.0.store(true, Ordering::Release)
+            }
+        }
+
+        let mut future = core::pin::pin!(future);
+        let flag = Arc::new(Flag(AtomicBool::new(true)));
+        let waker = Waker::from(flag.clone());
+        loop {
+            if flag.0.swap(false, Ordering::AcqRel)
+                && let Poll::Ready(output) = future.as_mut().poll(&mut Context::from_waker(&waker))
+            {
+                return output;
+            }
+            if self.run_until_stalled() > 0 || flag.0.load(Ordering::Acquire) {
+                continue;
+            }
+            if !self.fire_next(u64::MAX) {
+                panic!("block_on: the future is pending but nothing is left to wake it");
+            }
+        }
+    }
+
+    /// Wake the delays with the earliest deadline if it is at or before `until`
+    fn fire_next(&self, until: u64) -> bool {
+        let mut timers = self.inner.timers.lock().unwrap();
+        let Some(&(deadline, _)) = timers.keys().next() else {
+            return false;
+        };
+        if deadline > until {
+            return false;
+        }
+        self.inner.now.fetch_max(deadline, Ordering::AcqRel);
+        let due: std::vec::Vec<_> = core::iter::from_fn(|| {
+            timers
+                .first_entry()
+                .filter(|entry| entry.key().0 == deadline)
+                .map(|entry| entry.remove())
+        })
+        .collect();
+        drop(timers);
+        due.into_iter().for_each(Waker::wake);
+        true
+    }
+}
+
+/// Resolves once virtual time reaches its deadline
+pub struct Delay {
+    inner: Arc<Inner>,
+    deadline: u64,
+    /// key of the registered waker
+    timer: Option<(u64, u64)>,
+}
+
+impl Future for Delay {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let mut timers = self.inner.timers.lock().unwrap();
+        if let Some(key) = self.timer {
+            timers.remove(&key);
+        }
+        if self.inner.now.load(Ordering::Acquire) >= self.deadline {
+            drop(timers);
+            self.timer = None;
+            return Poll::Ready(());
+        }
+        let key = (
+            self.deadline,
+            self.inner.next_id.fetch_add(1, Ordering::Relaxed),
+        );
+        timers.insert(key, cx.waker().clone());
+        drop(timers);
+        self.timer = Some(key);
+        Poll::Pending
+    }
+}
+
+impl Drop for Delay {
+    fn drop(&mut self) {
+        if let Some(key) = self.timer {
+            self.inner.timers.lock().unwrap().remove(&key);
+        }
+    }
+}
+
+impl Timer for SimRuntime {
+    type DelayFuture = Delay;
+
+    fn delay_ms(&self, millis: u32) -> Self::DelayFuture {
+        self.delay_us(millis as u64 * 1000)
+    }
+
+    fn delay_us(&self, micros: u64) -> Self::DelayFuture {
+        Delay {
+            inner: self.inner.clone(),
+            deadline: self.time_us().saturating_add(micros),
+            timer: None,
+        }
+    }
+}
+
+impl Clock for SimRuntime {
+    /// Virtual time
+    fn now_us(&self) -> u64 {
+        self.time_us()
+    }
+}
+
+impl Spawner for SimRuntime {
+    type Handle = ();
+    type Error = ();
+
+    /// The future is first polled by the next `run_until_stalled`, `advance_*`, `run` or `block_on`
+    fn spawn<F>(&self, future: F) -> Result<Self::Handle, Self::Error>
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let id = self.inner.next_id.fetch_add(1, Ordering::Relaxed);
+        let task = SimTask {
+            future: Some(Box::pin(future)),
+            waker: Arc::new(TaskWaker {
+                id,
+                queued: AtomicBool::new(true),
+                ready: self.inner.ready.clone(),
+            }),
+        };
+        self.inner.tasks.lock().unwrap().insert(id, task);
+        self.inner.ready.lock().unwrap().push_back(id);
+        Ok(())
+    }
+}
+
+impl WakerBuilder for SimRuntime {
+    fn build_waker(&self) -> Waker {
+        Waker::noop().clone()
+    }
+}
+
+impl FlowRuntime for SimRuntime {
+    /// Goes to the back of the queue of woken futures
+    async fn yield_now(&self) {
+        super::yield_once().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::vec::Vec;
+
+    /// Spawns a future that records the virtual time once its delay of `millis` is over
+    fn wait(sim: &SimRuntime, log: &Arc<Mutex<Vec<(u32, u64)>>>, millis: u32) {
+        let (sim_, log) = (sim.clone(), log.clone());
+        let delay = sim.delay_ms(millis);
+        sim.spawn(async move {
+            delay.await;
+            log.lock().unwrap().push((millis, sim_.time_us()));
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn timers_fire_in_deadline_order() {
+        let sim = SimRuntime::new();
+        let log = Arc::new(Mutex::new(Vec::new()));
+        for millis in [30, 10, 20, 10] {
+            wait(&sim, &log, millis);
+        }
+
+        sim.advance_ms(15);
+        assert_eq!(sim.time_us(), 15_000);
+        assert_eq!(*log.lock().unwrap(), [(10, 10_000), (10, 10_000)]);
+
+        // each delay fires at its own deadline on the way
+        sim.advance_us(20_000);
+        assert_eq!(sim.time_us(), 35_000);
+        assert_eq!(log.lock().unwrap()[2..], [(20, 20_000), (30, 30_000)]);
+        assert_eq!(sim.tasks(), 0);
+    }
+
+    #[test]
+    fn run_until_stalled_keeps_the_time() {
+        let sim = SimRuntime::new();
+        let log = Arc::new(Mutex::new(Vec::new()));
+        wait(&sim, &log, 10);
+
+        assert_eq!(sim.run_until_stalled(), 1);
+        assert_eq!(sim.run_until_stalled(), 0);
+        assert_eq!(sim.time_us(), 0);
+        assert_eq!(sim.next_deadline_us(), Some(10_000));
+        assert!(log.lock().unwrap().is_empty());
+
+        // `run` jumps to the deadline
+        sim.run();
+        assert_eq!(sim.time_us(), 10_000);
+        assert_eq!(*log.lock().unwrap(), [(10, 10_000)]);
+        assert_eq!(sim.tasks(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "nothing is left to wake it")]
+    fn block_on_panics_when_stalled() {
+        SimRuntime::new().block_on(core::future::pending::<()>());
+    }
+
+    #[test]
+    fn block_on_jumps_to_deadlines() {
+        let sim = SimRuntime::new();
+        sim.block_on(sim.delay_ms(5));
+        assert_eq!(sim.time_us(), 5_000);
+    }
+
+    #[test]
+    fn duplicate_wakes_poll_once() {
+        let sim = SimRuntime::new();
+        let polls = Arc::new(AtomicU64::new(0));
+        let counted = polls.clone();
+        sim.spawn(core::future::poll_fn(move |cx| {
+            if counted.fetch_add(1, Ordering::Relaxed) > 0 {
+                return Poll::Ready(());
+            }
+            cx.waker().wake_by_ref();
+            cx.waker().wake_by_ref();
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }))
+        .unwrap();
+
+        assert_eq!(sim.run_until_stalled(), 2);
+        assert_eq!(polls.load(Ordering::Relaxed), 2);
+        assert_eq!(sim.tasks(), 0);
+    }
+
+    #[test]
+    fn fire_next_wakes_the_earliest_deadline() {
+        let sim = SimRuntime::new();
+        let log = Arc::new(Mutex::new(Vec::new()));
+        for millis in [20, 10, 10] {
+            wait(&sim, &log, millis);
+        }
+        sim.run_until_stalled();
+
+        // not due before `until`
+        assert!(!sim.fire_next(5_000));
+        assert_eq!(sim.time_us(), 0);
+
+        assert!(sim.fire_next(u64::MAX));
+        assert_eq!(sim.time_us(), 10_000);
+        assert_eq!(sim.next_deadline_us(), Some(20_000));
+        // the delays are woken, not polled
+        assert!(log.lock().unwrap().is_empty());
+        assert_eq!(sim.run_until_stalled(), 2);
+        assert_eq!(log.lock().unwrap().len(), 2);
+
+        assert!(sim.fire_next(u64::MAX));
+        assert!(!sim.fire_next(u64::MAX));
+        sim.run_until_stalled();
+        assert_eq!(sim.tasks(), 0);
+    }
+}
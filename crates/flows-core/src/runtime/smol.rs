@@ -0,0 +1,99 @@
+use super::{Clock, FlowRuntime, Spawner, Timer, WakerBuilder};
+use async_executor::{Executor, Task};
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll, Waker};
+use core::time::Duration;
+use std::sync::Arc;
+use std::time::Instant;
+
+/// Runs flows on an `async_executor::Executor`, with `async_io` timers
+/// The executor only makes progress while something runs it, e.g. `async_io::block_on(executor.run(..))`
+#[derive(Clone)]
+pub struct SmolRuntime {
+    executor: Arc<Executor<'static>>,
+    /// readings of the clock count from here
+    start: Instant,
+}
+
+impl SmolRuntime {
+    pub fn new(executor: Arc<Executor<'static>>) -> Self {
+        Self {
+            executor,
+            start: Instant::now(),
+        }
+    }
+
+    /// The executor flows are spawned on
+    pub fn executor(&self) -> &Arc<Executor<'static>> {
+        &self.executor
+    }
+}
+
+impl Default for SmolRuntime {
+    fn default() -> Self {
+        Self::new(Arc::new(Executor::new()))
+    }
+}
+
+impl core::fmt::Debug for SmolRuntime {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("SmolRuntime").finish_non_exhaustive()
+    }
+}
+
+/// `async_io::Timer` resolving to `()`
+pub struct Delay(async_io::Timer);
+
+impl Future for Delay {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        Pin::new(&mut self.0).poll(cx).map(|_| ())
+    }
+}
+
+impl Timer for SmolRuntime {
+    type DelayFuture = Delay;
+
+    fn delay_ms(&self, millis: u32) -> Self::DelayFuture {
+        Delay(async_io::Timer::after(Duration::from_millis(millis as u64)))
+    }
+
+    fn delay_us(&self, micros: u64) -> Self::DelayFuture {
+        Delay(async_io::Timer::after(Duration::from_micros(micros)))
+    }
+}
+
+impl Clock for SmolRuntime {
+    /// Time since the runtime was created, on the monotonic clock its timers run on
+    fn now_us(&self) -> u64 {
+        self.start.elapsed().as_micros() as u64
+    }
+}
+
+impl Spawner for SmolRuntime {
+    /// Dropping the task cancels the flow, `Task::detach` lets it run on its own
+    type Handle = Task<()>;
+    type Error = ();
+
+    fn spawn<F>(&self, future: F) -> Result<Self::Handle, Self::Error>
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        Ok(self.executor.spawn(future))
+    }
+}
+
+impl WakerBuilder for SmolRuntime {
+    fn build_waker(&self) -> Waker {
+        // smol tasks are woken by their own wakers, there is nothing to wake here
+        Waker::noop().clone()
+    }
+}
+
+impl FlowRuntime for SmolRuntime {
+    async fn yield_now(&self) {
+        super::yield_once().await
+    }
+}
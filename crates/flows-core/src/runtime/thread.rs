@@ -0,0 +1,382 @@
+use super::{Clock, FlowRuntime, Spawner, Timer, WakerBuilder};
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll, Waker};
+use core::time::Duration;
+use portable_atomic::{AtomicBool, AtomicU64, Ordering};
+use std::boxed::Box;
+use std::collections::{BTreeMap, VecDeque};
+use std::panic::{AssertUnwindSafe, catch_unwind};
+use std::sync::{Arc, Condvar, Mutex, Weak};
+use std::task::Wake;
+use std::thread;
+use std::time::Instant;
+
+/// Runtime on plain std threads: a pool of workers polls the spawned futures,
+/// a timer thread wakes the delays once they are due
+/// The threads stop on `shutdown`, or once the last clone of the runtime is dropped. Spawned flows
+/// hold a clone through their controllers, so a flow that never ends keeps the threads running
+/// until `shutdown` drops it.
+/// A future that panics is dropped, the worker polling it keeps going and the flow's join resolves
+/// to `JoinError::Panicked`
+#[derive(Clone)]
+pub struct ThreadRuntime {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    queue: Arc<Queue>,
+    /// every task that has not finished yet, by id
+    tasks: Mutex<BTreeMap<u64, Arc<Task>>>,
+    timers: Arc<Timers>,
+    next_id: AtomicU64,
+    /// readings of the clock count from here
+    start: Instant,
+}
+
+/// used by the workers, the threads hold on to it rather than the runtime
+struct Queue {
+    /// tasks woken and waiting for a worker, `None` once the runtime is dropped
+    tasks: Mutex<Option<VecDeque<Arc<Task>>>>,
+    queued: Condvar,
+}
+
+/// used by the timer thread and the delays
+struct Timers {
+    /// wakers of pending delays by deadline, in the order they were registered,
+    /// `None` once the runtime is dropped
+    wakers: Mutex<Option<BTreeMap<(Instant, u64), Waker>>>,
+    /// notified when a delay is due before the one the timer thread waits for
+    changed: Condvar,
+    next_id: AtomicU64,
+}
+
+impl Inner {
+    /// the threads find nothing left to do and return, what was left is dropped outside the locks
+    fn stop(&self) {
+        let _queued = self.queue.tasks.lock().unwrap().take();
+        self.queue.queued.notify_all();
+        let _timers = self.timers.wakers.lock().unwrap().take();
+        self.timers.changed.notify_one();
+    }
+}
+
+impl Drop for Inner {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+std::thread_local! {
+    /// the task the worker on this thread is polling
+    static POLLING: core::cell::Cell<Option<u64>> = const { core::cell::Cell::new(None) };
+}
+
+struct Task {
+    id: u64,
+    future: Mutex<Option<Pin<Box<dyn Future<Output = ()> + Send>>>>,
+    /// set while the task sits in the queue, so it is queued once however often it is woken
+    queued: AtomicBool,
+    inner: Weak<Inner>,
+}
+
+impl Wake for Task {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref()
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        if self.queued.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        if let Some(inner) = self.inner.upgrade()
+            && let Some(queue) = inner.queue.tasks.lock().unwrap().as_mut()
+        {
+            queue.push_back(self.clone());
+            inner.queue.queued.notify_one();
+        }
+    }
+}
+
+/// Wakes every task of the runtime
+struct WakeAll(Weak<Inner>);
+
+impl Wake for WakeAll {
+    fn wake(self: Arc<Self>) {
+        if let Some(inner) = self.0.upgrade() {
+            let tasks: std::vec::Vec<_> = inner.tasks.lock().unwrap().values().cloned().collect();
+            tasks.iter().for_each(Wake::wake_by_ref);
+        }
+    }
+}
+
+/// Unparks the thread blocked in `block_on`
+struct Unpark(thread::Thread);
+
+impl Wake for Unpark {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark()
+    }
+}
+
+impl core::fmt::Debug for ThreadRuntime {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("ThreadRuntime")
+            .field("tasks", &self.inner.tasks.lock().unwrap().len())
+            .finish()
+    }
+}
+
+impl ThreadRuntime {
+    /// Start `workers` worker threads, at least one, and the timer thread
+    pub fn new(workers: usize) -> Self {
+        let inner = Arc::new(Inner {
+            queue: Arc::new(Queue {
+                tasks: Mutex::new(Some(VecDeque::new())),
+                queued: Condvar::new(),
+            }),
+            tasks: Mutex::new(BTreeMap::new()),
+            timers: Arc::new(Timers {
+                wakers: Mutex::new(Some(BTreeMap::new())),
+                changed: Condvar::new(),
+                next_id: AtomicU64::new(0),
+            }),
+            next_id: AtomicU64::new(0),
+            start: Instant::now(),
+        });
+        for n in 0..workers.max(1) {
+            let queue = inner.queue.clone();
+            let runtime = Arc::downgrade(&inner);
+            thread::Builder::new()
+                .name(std::format!("flows-worker-{}", n))
+                .spawn(move || queue.work(runtime))
+                .expect("could not start a worker thread");
+        }
+        let timers = inner.timers.clone();
+        thread::Builder::new()
+            .name("flows-timer".into())
+            .spawn(move || timers.fire())
+            .expect("could not start the timer thread");
+        Self { inner }
+    }
+
+    /// Run `future` to completion on the calling thread, parking it while the future is pending
+    /// Spawned futures keep running on the workers meanwhile
+    pub fn block_on<F: Future>(&self, future: F) -> F::Output {
+        let mut future = core::pin::pin!(future);
+        let waker = Waker::from(Arc::new(Unpark(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+            thread::park();
+        }
+    }
+
+    /// Stop the threads and drop every spawned future that has not finished yet
+    /// Joins of the flows dropped resolve to `JoinError::Dropped`, spawning afterwards fails.
+    /// Called from a spawned future, that future is dropped once its current poll returns.
+    pub fn shutdown(&self) {
+        self.inner.stop();
+        let tasks = core::mem::take(&mut *self.inner.tasks.lock().unwrap());
+        let current = POLLING.get();
+        for task in tasks.into_values() {
+            // the worker polling it drops it, a worker polling another one is waited for
+            if Some(task.id) != current {
+                let future = task.future.lock().unwrap().take();
+                drop(future);
+            }
+        }
+    }
+
+    /// Spawned futures that have not finished yet
+    pub fn tasks(&self) -> usize {
+        self.inner.tasks.lock().unwrap().len()
+    }
+}
+
+impl Default for ThreadRuntime {
+    /// One worker per core
+    fn default() -> Self {
+        Self::new(thread::available_parallelism().map_or(1, |n| n.get()))
+    }
+}
+
+impl Queue {
+    /// used by the worker threads, returns once the runtime is dropped
+    fn work(&self, runtime: Weak<Inner>) {
+        loop {
+            let task = {
+                let mut queue = self.tasks.lock().unwrap();
+                loop {
+                    match queue.as_mut().map(VecDeque::pop_front) {
+                        Some(Some(task)) => break task,
+                        Some(None) => queue = self.queued.wait(queue).unwrap(),
+                        None => return,
+                    }
+                }
+            };
+            // cleared before the poll, so a wake during the poll queues the task again
+            task.queued.store(false, Ordering::Release);
+            // a task woken while another worker polls it waits here for its turn
+            let mut future = task.future.lock().unwrap();
+            let Some(polled) = future.as_mut() else {
+                continue;
+            };
+            let waker = Waker::from(task.clone());
+            POLLING.set(Some(task.id));
+            // a panicking future is done as well, the hook has reported the panic already
+            let done = catch_unwind(AssertUnwindSafe(|| {
+                polled.as_mut().poll(&mut Context::from_waker(&waker))
+            }))
+            .map_or(true, |poll| poll.is_ready());
+            POLLING.set(None);
+            // a shutdown during the poll left this one to the worker
+            if done || self.tasks.lock().unwrap().is_none() {
+                *future = None;
+                if let Some(runtime) = runtime.upgrade() {
+                    runtime.tasks.lock().unwrap().remove(&task.id);
+                }
+            }
+        }
+    }
+}
+
+impl Timers {
+    /// used by the timer thread, returns once the runtime is dropped
+    fn fire(&self) {
+        let mut guard = self.wakers.lock().unwrap();
+        loop {
+            let now = Instant::now();
+            let Some(timers) = guard.as_mut() else {
+                return;
+            };
+            match timers.first_key_value() {
+                Some((&(deadline, _), _)) if deadline <= now => {
+                    let (_, waker) = timers.pop_first().unwrap();
+                    waker.wake();
+                }
+                Some((&(deadline, _), _)) => {
+                    guard = self.changed.wait_timeout(guard, deadline - now).unwrap().0;
+                }
+                None => guard = self.changed.wait(guard).unwrap(),
+            }
+        }
+    }
+}
+
+/// Resolves once its deadline has passed, woken by the timer thread
+pub struct Delay {
+    timers: Arc<Timers>,
+    deadline: Instant,
+    /// key of the registered waker
+    timer: Option<(Instant, u64)>,
+}
+
+impl Future for Delay {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let mut guard = self.timers.wakers.lock().unwrap();
+        let Some(timers) = guard.as_mut() else {
+            // nothing will fire it anymore
+            return Poll::Pending;
+        };
+        if let Some(key) = self.timer {
+            timers.remove(&key);
+        }
+        if Instant::now() >= self.deadline {
+            drop(guard);
+            self.timer = None;
+            return Poll::Ready(());
+        }
+        let key = (
+            self.deadline,
+            self.timers.next_id.fetch_add(1, Ordering::Relaxed),
+        );
+        timers.insert(key, cx.waker().clone());
+        let earliest = timers
+            .first_key_value()
+            .is_some_and(|(first, _)| *first == key);
+        drop(guard);
+        if earliest {
+            self.timers.changed.notify_one();
+        }
+        self.timer = Some(key);
+        Poll::Pending
+    }
+}
+
+impl Drop for Delay {
+    fn drop(&mut self) {
+        if let Some(key) = self.timer
+            && let Some(timers) = self.timers.wakers.lock().unwrap().as_mut()
+        {
+            timers.remove(&key);
+        }
+    }
+}
+
+impl Timer for ThreadRuntime {
+    type DelayFuture = Delay;
+
+    fn delay_ms(&self, millis: u32) -> Self::DelayFuture {
+        self.delay_us(millis as u64 * 1000)
+    }
+
+    fn delay_us(&self, micros: u64) -> Self::DelayFuture {
+        Delay {
+            timers: self.inner.timers.clone(),
+            deadline: Instant::now() + Duration::from_micros(micros),
+            timer: None,
+        }
+    }
+}
+
+impl Clock for ThreadRuntime {
+    /// Time since the runtime was created, on the monotonic clock its timers run on
+    fn now_us(&self) -> u64 {
+        self.inner.start.elapsed().as_micros() as u64
+    }
+}
+
+impl Spawner for ThreadRuntime {
+    type Handle = ();
+    type Error = ();
+
+    /// Fails once the runtime was shut down
+    fn spawn<F>(&self, future: F) -> Result<Self::Handle, Self::Error>
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let task = Arc::new(Task {
+            id: self.inner.next_id.fetch_add(1, Ordering::Relaxed),
+            future: Mutex::new(Some(Box::pin(future))),
+            queued: AtomicBool::new(false),
+            inner: Arc::downgrade(&self.inner),
+        });
+        // checked under the lock, a shutdown takes the tasks after it stopped the queue
+        let mut tasks = self.inner.tasks.lock().unwrap();
+        if self.inner.queue.tasks.lock().unwrap().is_none() {
+            return Err(());
+        }
+        tasks.insert(task.id, task.clone());
+        drop(tasks);
+        task.wake();
+        Ok(())
+    }
+}
+
+impl WakerBuilder for ThreadRuntime {
+    /// Wakes every future spawned on this runtime that has not finished yet
+    fn build_waker(&self) -> Waker {
+        Waker::from(Arc::new(WakeAll(Arc::downgrade(&self.inner))))
+    }
+}
+
+impl FlowRuntime for ThreadRuntime {
+    async fn yield_now(&self) {
+        super::yield_once().await
+    }
+}
@@ -1,15 +1,20 @@
-use super::{FlowRuntime, Spawner, Timer, WakerBuilder};
+use super::{Clock, FlowRuntime, LocalSpawner, Spawner, Timer, WakerBuilder};
 use core::future::Future;
 use core::task::Waker;
 use core::time::Duration;
-use tokio::task::JoinHandle;
+use tokio::task::{JoinHandle, LocalSet};
 
 #[derive(Debug, Clone)]
-pub struct TokioRuntime;
+pub struct TokioRuntime {
+    /// readings of the clock count from here
+    start: tokio::time::Instant,
+}
 
 impl TokioRuntime {
     pub fn new() -> Self {
-        Self
+        Self {
+            start: tokio::time::Instant::now(),
+        }
     }
 }
 
@@ -25,6 +30,13 @@ impl Timer for TokioRuntime {
     }
 }
 
+impl Clock for TokioRuntime {
+    /// Time since the runtime was created on tokio's clock, which stands still while `tokio::time::pause`d
+    fn now_us(&self) -> u64 {
+        self.start.elapsed().as_micros() as u64
+    }
+}
+
 impl Spawner for TokioRuntime {
     type Handle = JoinHandle<()>;
     type Error = ();
@@ -37,6 +49,33 @@ impl Spawner for TokioRuntime {
     }
 }
 
+/// Spawns onto the `LocalSet` the caller runs in
+/// Panics outside of one, like `tokio::task::spawn_local`, spawn with the `LocalSet` itself to avoid that
+impl LocalSpawner for TokioRuntime {
+    type Handle = JoinHandle<()>;
+    type Error = ();
+
+    fn spawn_local<F>(&self, future: F) -> Result<Self::Handle, Self::Error>
+    where
+        F: Future<Output = ()> + 'static,
+    {
+        Ok(tokio::task::spawn_local(future))
+    }
+}
+
+/// Spawns onto the set from anywhere, its flows run while the set is run or awaited
+impl LocalSpawner for LocalSet {
+    type Handle = JoinHandle<()>;
+    type Error = ();
+
+    fn spawn_local<F>(&self, future: F) -> Result<Self::Handle, Self::Error>
+    where
+        F: Future<Output = ()> + 'static,
+    {
+        Ok(LocalSet::spawn_local(self, future))
+    }
+}
+
 impl WakerBuilder for TokioRuntime {
     fn build_waker(&self) -> Waker {
         std::task::Waker::from(std::sync::Arc::new(TokioWaker))
@@ -0,0 +1,193 @@
+//! Sequences, joins and selects of flows, and the commands passed on to their members
+
+use flows_core::runtime::Spawner;
+use flows_core::runtime::sim::SimRuntime;
+use flows_core::{
+    ArcSlot, CommandError, FlowOutcome, FlowState, FnController, FnDataHandle, Member, join_all,
+    select, sequence,
+};
+use std::sync::{Arc, Mutex};
+
+type Ctrl = FnController<SimRuntime, (), 4>;
+type Data = FnDataHandle<(), (), 4>;
+type Slot = ArcSlot<(), (), (), 4, 4>;
+type Outcomes = Arc<Mutex<Option<Vec<FlowOutcome<u32, &'static str>>>>>;
+
+/// Completes with `millis` once they passed
+async fn wait(millis: u32, ctrl: Ctrl, _data: Data) -> Result<u32, &'static str> {
+    ctrl.delay_ms(millis).await;
+    Ok(millis)
+}
+
+/// Completes with the time it started at
+async fn started_at(sim: SimRuntime, _ctrl: Ctrl, _data: Data) -> Result<u32, &'static str> {
+    Ok((sim.time_us() / 1000) as u32)
+}
+
+/// Fails once `millis` passed
+async fn fail(millis: u32, ctrl: Ctrl, _data: Data) -> Result<u32, &'static str> {
+    ctrl.delay_ms(millis).await;
+    Err("failed")
+}
+
+/// Spawns `composite`, the outcomes show up once it resolved
+fn spawn<C>(sim: &SimRuntime, composite: C) -> Outcomes
+where
+    C: Future<Output = Vec<FlowOutcome<u32, &'static str>>> + Send + 'static,
+{
+    let outcomes = Outcomes::default();
+    let out = outcomes.clone();
+    sim.spawn(async move { *out.lock().unwrap() = Some(composite.await) })
+        .unwrap();
+    outcomes
+}
+
+#[test]
+fn sequence_runs_different_functions_in_turn() {
+    let sim = SimRuntime::new();
+    let (first, _) = Member::new(&sim, wait, 30, Slot::new());
+    let (second, _) = Member::new(&sim, started_at, sim.clone(), Slot::new());
+    let (both, ctrl) = sequence([first, second]);
+    let outcomes = spawn(&sim, both);
+
+    sim.advance_ms(10);
+    assert_eq!(ctrl.state(), FlowState::Running);
+    sim.run();
+    assert_eq!(ctrl.state(), FlowState::Completed);
+    // the second one starts once the first completed
+    assert_eq!(
+        outcomes.lock().unwrap().take(),
+        Some(vec![FlowOutcome::Completed(30), FlowOutcome::Completed(30)])
+    );
+}
+
+#[test]
+fn sequence_stops_at_a_failure() {
+    let sim = SimRuntime::new();
+    let (first, _) = Member::new(&sim, fail, 10, Slot::new());
+    let (second, _) = Member::new(&sim, wait, 10, Slot::new());
+    let (both, ctrl) = sequence([first, second]);
+    let outcomes = spawn(&sim, both);
+
+    sim.run();
+    assert_eq!(ctrl.state(), FlowState::Error);
+    assert_eq!(
+        outcomes.lock().unwrap().take(),
+        Some(vec![FlowOutcome::Failed("failed"), FlowOutcome::Cancelled])
+    );
+}
+
+#[test]
+fn join_all_waits_for_every_member() {
+    let sim = SimRuntime::new();
+    let (a, _) = Member::new(&sim, wait, 10, Slot::new());
+    let (b, _) = Member::new(&sim, wait, 30, Slot::new());
+    let (both, ctrl) = join_all([a, b]);
+    let outcomes = spawn(&sim, both);
+
+    sim.advance_ms(20);
+    assert_eq!(ctrl.state(), FlowState::Running);
+    sim.run();
+    assert_eq!(sim.time_us(), 30_000);
+    assert_eq!(ctrl.state(), FlowState::Completed);
+    assert_eq!(
+        outcomes.lock().unwrap().take(),
+        Some(vec![FlowOutcome::Completed(10), FlowOutcome::Completed(30)])
+    );
+}
+
+#[test]
+fn join_all_cancels_the_rest_on_a_failure() {
+    let sim = SimRuntime::new();
+    let (a, _) = Member::new(&sim, wait, 100, Slot::new());
+    let (b, _) = Member::new(&sim, fail, 10, Slot::new());
+    let (both, ctrl) = join_all([a, b]);
+    let outcomes = spawn(&sim, both);
+
+    sim.run();
+    assert_eq!(sim.time_us(), 10_000);
+    assert_eq!(ctrl.state(), FlowState::Error);
+    assert_eq!(
+        outcomes.lock().unwrap().take(),
+        Some(vec![FlowOutcome::Cancelled, FlowOutcome::Failed("failed")])
+    );
+}
+
+#[test]
+fn select_takes_the_first_to_end() {
+    let sim = SimRuntime::new();
+    let (slow, _) = Member::new(&sim, wait, 50, Slot::new());
+    let (fast, _) = Member::new(&sim, wait, 20, Slot::new());
+    let (race, ctrl) = select([slow, fast]);
+    let outcomes = spawn(&sim, race);
+
+    sim.run();
+    assert_eq!(sim.time_us(), 20_000);
+    assert_eq!(ctrl.state(), FlowState::Completed);
+    assert_eq!(
+        outcomes.lock().unwrap().take(),
+        Some(vec![FlowOutcome::Cancelled, FlowOutcome::Completed(20)])
+    );
+}
+
+#[test]
+fn commands_resolve_once_the_members_applied_them() {
+    let sim = SimRuntime::new();
+    let (slot_a, slot_b) = (Slot::new(), Slot::new());
+    let (a, _) = Member::new(&sim, wait, 30, slot_a.clone());
+    let (b, _) = Member::new(&sim, wait, 500, slot_b.clone());
+    let (both, ctrl) = join_all([a, b]);
+    let outcomes = spawn(&sim, both);
+    sim.advance_ms(10);
+
+    assert_eq!(sim.block_on(ctrl.pause()), Ok(()));
+    assert_eq!(slot_a.user().state(), FlowState::Paused);
+    assert_eq!(slot_b.user().state(), FlowState::Paused);
+    assert_eq!(ctrl.state(), FlowState::Paused);
+    // every member rejects a second pause
+    assert_eq!(
+        sim.block_on(ctrl.pause()),
+        Err(CommandError::Rejected(FlowState::Paused))
+    );
+
+    // the members do not run while paused, even past their delay
+    sim.advance_ms(100);
+    assert_eq!(slot_a.user().state(), FlowState::Paused);
+    assert_eq!(outcomes.lock().unwrap().as_ref(), None);
+
+    // the first one completes right away since its delay passed
+    assert_eq!(sim.block_on(ctrl.resume()), Ok(()));
+    assert_eq!(slot_a.user().state(), FlowState::Completed);
+    assert_eq!(slot_b.user().state(), FlowState::Running);
+
+    assert_eq!(sim.block_on(ctrl.cancel()), Ok(()));
+    assert_eq!(slot_b.user().state(), FlowState::Cancelled);
+    sim.run_until_stalled();
+    assert_eq!(ctrl.state(), FlowState::Cancelled);
+    assert_eq!(
+        outcomes.lock().unwrap().take(),
+        Some(vec![FlowOutcome::Completed(30), FlowOutcome::Cancelled])
+    );
+}
+
+#[test]
+fn paused_sequence_starts_nothing() {
+    let sim = SimRuntime::new();
+    let (first, _) = Member::new(&sim, wait, 10, Slot::new());
+    let (second, _) = Member::new(&sim, started_at, sim.clone(), Slot::new());
+    let (both, ctrl) = sequence([first, second]);
+    let outcomes = spawn(&sim, both);
+    sim.run_until_stalled();
+
+    assert_eq!(sim.block_on(ctrl.pause()), Ok(()));
+    sim.advance_ms(40);
+    assert_eq!(ctrl.state(), FlowState::Paused);
+
+    assert_eq!(sim.block_on(ctrl.resume()), Ok(()));
+    sim.run();
+    // the first one finished its delay of 10ms once resumed at 40ms
+    assert_eq!(
+        outcomes.lock().unwrap().take(),
+        Some(vec![FlowOutcome::Completed(10), FlowOutcome::Completed(40)])
+    );
+}
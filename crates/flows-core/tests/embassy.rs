@@ -0,0 +1,121 @@
+//! Drives flows on the std embassy executor from another thread, through the `Send` spawner,
+//! and flows that are not `Send` through the executor's own spawner
+
+use embassy_futures::block_on;
+use flows_core::runtime::FlowRuntime;
+use flows_core::runtime::embassy::{EmbassyRuntime, FlowTasks};
+use flows_core::{ArcSlot, FlowOutcome, FlowState, FnController, FnDataHandle};
+use std::cell::Cell;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+static TASKS: FlowTasks<2, 2048> = FlowTasks::new();
+static LOCAL_TASKS: FlowTasks<1, 2048> = FlowTasks::new();
+
+type Runtime = EmbassyRuntime<2048>;
+
+async fn tick(
+    ticks: Arc<AtomicU32>,
+    ctrl: FnController<Runtime, (), 4>,
+    _data: FnDataHandle<(), (), 4>,
+) -> Result<(), ()> {
+    loop {
+        ticks.fetch_add(1, Ordering::Relaxed);
+        ctrl.delay_ms(1).await;
+    }
+}
+
+/// Counts its ticks in an `Rc` held across the delays
+async fn local_tick(
+    ticks: Arc<AtomicU32>,
+    ctrl: FnController<Runtime, (), 4>,
+    _data: FnDataHandle<(), (), 4>,
+) -> Result<(), ()> {
+    let local = Rc::new(Cell::new(0));
+    loop {
+        local.set(local.get() + 1);
+        ticks.store(local.get(), Ordering::Relaxed);
+        ctrl.delay_ms(1).await;
+    }
+}
+
+fn wait_for(what: &str, done: impl Fn() -> bool) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !done() {
+        assert!(Instant::now() < deadline, "timed out waiting for {what}");
+        thread::sleep(Duration::from_millis(1));
+    }
+}
+
+#[test]
+fn pause_resume_cancel() {
+    let (sender, runtime) = mpsc::channel();
+    thread::spawn(move || {
+        let executor = Box::leak(Box::new(embassy_executor::Executor::new()));
+        executor.run(|spawner| {
+            sender
+                .send(Runtime::new(spawner.make_send(), &TASKS))
+                .unwrap()
+        })
+    });
+    let runtime: Runtime = runtime.recv().unwrap();
+
+    let ticks = Arc::new(AtomicU32::new(0));
+    let slot: ArcSlot<(), (), (), 4, 4> = ArcSlot::new();
+    let flow = runtime.spawn_flow(tick, ticks.clone(), slot).unwrap();
+    wait_for("the first ticks", || ticks.load(Ordering::Relaxed) > 2);
+    assert_eq!(flow.state(), FlowState::Running);
+
+    // acknowledged once the flow on the executor thread applied it
+    assert!(block_on(flow.pause()).is_ok());
+    assert_eq!(flow.state(), FlowState::Paused);
+    let paused_at = ticks.load(Ordering::Relaxed);
+    thread::sleep(Duration::from_millis(20));
+    // the function is not polled while the flow is paused
+    assert_eq!(ticks.load(Ordering::Relaxed), paused_at);
+
+    assert!(block_on(flow.resume()).is_ok());
+    assert_eq!(flow.state(), FlowState::Running);
+    let resumed_at = ticks.load(Ordering::Relaxed);
+    wait_for("ticks after the resume", || {
+        ticks.load(Ordering::Relaxed) > resumed_at + 2
+    });
+
+    assert!(block_on(flow.cancel()).is_ok());
+    assert_eq!(flow.state(), FlowState::Cancelled);
+}
+
+#[test]
+fn local_flow() {
+    let ticks = Arc::new(AtomicU32::new(0));
+    let (sender, flow) = mpsc::channel();
+    let counted = ticks.clone();
+    thread::spawn(move || {
+        let executor = Box::leak(Box::new(embassy_executor::Executor::new()));
+        executor.run(|spawner| {
+            let runtime = Runtime::new(spawner.make_send(), &LOCAL_TASKS);
+            // on the executor's thread, where the function stays
+            let local = runtime.local(spawner);
+            let slot: ArcSlot<(), (), (), 4, 4> = ArcSlot::new();
+            let flow = runtime.spawn_local_flow(&local, local_tick, counted, slot);
+            sender.send(flow.unwrap()).unwrap()
+        })
+    });
+    let flow = flow.recv().unwrap();
+
+    wait_for("the first ticks", || ticks.load(Ordering::Relaxed) > 2);
+    assert!(block_on(flow.pause()).is_ok());
+    assert_eq!(flow.state(), FlowState::Paused);
+    assert!(block_on(flow.resume()).is_ok());
+    let resumed_at = ticks.load(Ordering::Relaxed);
+    wait_for("ticks after the resume", || {
+        ticks.load(Ordering::Relaxed) > resumed_at + 2
+    });
+
+    assert!(block_on(flow.cancel()).is_ok());
+    assert_eq!(block_on(flow.join()), Ok(FlowOutcome::Cancelled));
+}
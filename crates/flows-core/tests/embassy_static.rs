@@ -0,0 +1,87 @@
+//! Launches and joins flows on embassy from static storage only, run it without `alloc`:
+//! `cargo test --no-default-features --features embassy --test embassy_static`
+//! and with the `journal` feature to check its timestamps too
+
+use embassy_futures::block_on;
+use flows_core::runtime::FlowRuntime;
+use flows_core::runtime::embassy::{EmbassyRuntime, FlowTasks};
+use flows_core::{FlowOutcome, FlowState, FnController, FnDataHandle, JoinCell, LaunchError, Slot};
+use std::sync::mpsc;
+use std::thread;
+
+static TASKS: FlowTasks<2, 2048> = FlowTasks::new();
+static SLOT: Slot<(), (), u32, 4, 4> = Slot::new();
+static JOIN: JoinCell<FlowOutcome<u32, ()>> = JoinCell::new();
+#[cfg(feature = "journal")]
+static JOURNALED: Slot<(), (), u32, 4, 4> = Slot::new();
+#[cfg(feature = "journal")]
+static JOURNALED_JOIN: JoinCell<FlowOutcome<u32, ()>> = JoinCell::new();
+
+type Runtime = EmbassyRuntime<2048>;
+
+/// Pushes `until` ticks, one per millisecond, and completes with how many it pushed
+async fn count(
+    until: u32,
+    ctrl: FnController<Runtime, (), 4>,
+    data: FnDataHandle<(), u32, 4>,
+) -> Result<u32, ()> {
+    for tick in 0..until {
+        let _ = data.try_push(tick);
+        ctrl.delay_ms(1).await;
+    }
+    Ok(until)
+}
+
+fn runtime() -> Runtime {
+    let (sender, runtime) = mpsc::channel();
+    thread::spawn(move || {
+        let executor = Box::leak(Box::new(embassy_executor::Executor::new()));
+        executor.run(|spawner| {
+            sender
+                .send(Runtime::new(spawner.make_send(), &TASKS))
+                .unwrap()
+        })
+    });
+    runtime.recv().unwrap()
+}
+
+#[test]
+fn launch_and_join() {
+    let runtime = runtime();
+
+    let flow = runtime.launch_flow(count, 3, &SLOT, &JOIN).unwrap();
+    assert_eq!(block_on(flow.join), Ok(FlowOutcome::Completed(3)));
+    assert_eq!(flow.ctrl.state(), FlowState::Completed);
+
+    // one run at a time
+    let flow = runtime.launch_flow(count, u32::MAX, &SLOT, &JOIN).unwrap();
+    assert!(matches!(
+        runtime.launch_flow(count, 1, &SLOT, &JOIN),
+        Err(LaunchError::Busy)
+    ));
+    assert!(block_on(flow.ctrl.cancel()).is_ok());
+    assert_eq!(block_on(flow.join), Ok(FlowOutcome::Cancelled));
+
+    // the slot is reused once the run ended
+    let flow = runtime.launch_flow(count, 1, &SLOT, &JOIN).unwrap();
+    assert_eq!(block_on(flow.join), Ok(FlowOutcome::Completed(1)));
+}
+
+#[cfg(feature = "journal")]
+#[test]
+fn journal_without_a_heap() {
+    let runtime = runtime();
+
+    let flow = runtime
+        .launch_flow(count, u32::MAX, &JOURNALED, &JOURNALED_JOIN)
+        .unwrap();
+    thread::sleep(std::time::Duration::from_millis(5));
+    assert!(block_on(flow.ctrl.cancel()).is_ok());
+    assert_eq!(block_on(flow.join), Ok(FlowOutcome::Cancelled));
+
+    // stamped with embassy's clock, which needs no runtime to read
+    let journal = flow.ctrl.journal();
+    assert_eq!(journal.len(), 1);
+    assert_eq!(journal[0].event, "cancel");
+    assert!(journal[0].at_us >= 5000);
+}
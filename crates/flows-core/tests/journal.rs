@@ -0,0 +1,53 @@
+//! What the journal of a flow keeps and when it says things happened, on virtual time
+
+use flows_core::runtime::FlowRuntime;
+use flows_core::runtime::sim::SimRuntime;
+use flows_core::{
+    ArcSlot, CommandError, FlowEventHandler, FlowState, FnController, FnDataHandle, JournalEntry,
+};
+
+/// Keeps the last 3 events only
+type Ctrl = FnController<SimRuntime, (), 4, (), FlowState, FlowEventHandler, 3>;
+type Slot = ArcSlot<(), (), (), 4, 4, (), FlowState, FlowEventHandler, 3>;
+
+async fn idle(_: (), ctrl: Ctrl, _data: FnDataHandle<(), (), 4>) {
+    loop {
+        ctrl.delay_ms(1000).await;
+    }
+}
+
+fn entry(at_ms: u64, event: &'static str, from: FlowState, to: FlowState) -> JournalEntry {
+    JournalEntry {
+        at_us: at_ms * 1000,
+        event,
+        from,
+        to,
+    }
+}
+
+#[test]
+fn keeps_the_last_events_stamped_with_the_runtime_clock() {
+    let sim = SimRuntime::new();
+    let flow = sim.spawn_flow(idle, (), Slot::new()).unwrap();
+
+    sim.advance_ms(10);
+    assert_eq!(sim.block_on(flow.pause()), Ok(()));
+    sim.advance_ms(5);
+    assert_eq!(
+        sim.block_on(flow.pause()),
+        Err(CommandError::Rejected(FlowState::Paused))
+    );
+    assert_eq!(sim.block_on(flow.resume()), Ok(()));
+    sim.advance_ms(5);
+    assert_eq!(sim.block_on(flow.cancel()), Ok(()));
+
+    // the first pause was pushed out
+    assert_eq!(
+        flow.ctrl().journal(),
+        [
+            entry(15, "pause", FlowState::Paused, FlowState::Paused),
+            entry(15, "resume", FlowState::Paused, FlowState::Running),
+            entry(20, "cancel", FlowState::Running, FlowState::Cancelled),
+        ]
+    );
+}
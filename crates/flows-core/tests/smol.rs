@@ -0,0 +1,100 @@
+//! Drives flows on an `async_executor::Executor` run by the test thread, with `async_io` timers
+
+use flows_core::runtime::smol::SmolRuntime;
+use flows_core::runtime::{Clock, FlowRuntime, Timer};
+use flows_core::{ArcSlot, FlowOutcome, FlowState, FnController, FnDataHandle};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{Duration, Instant};
+
+async fn tick(
+    ticks: Arc<AtomicU32>,
+    ctrl: FnController<SmolRuntime, (), 4>,
+    _data: FnDataHandle<(), (), 4>,
+) -> Result<(), ()> {
+    loop {
+        ticks.fetch_add(1, Ordering::Relaxed);
+        ctrl.delay_ms(1).await;
+    }
+}
+
+async fn sleep(
+    millis: u32,
+    ctrl: FnController<SmolRuntime, (), 4>,
+    _data: FnDataHandle<(), (), 4>,
+) -> Result<u32, ()> {
+    ctrl.delay_ms(millis).await;
+    Ok(millis)
+}
+
+/// Run `test` on the runtime's executor until it is done
+fn run<T>(test: impl AsyncFnOnce(SmolRuntime) -> T) -> T {
+    let runtime = SmolRuntime::default();
+    let executor = runtime.executor().clone();
+    async_io::block_on(executor.run(test(runtime)))
+}
+
+async fn wait_for(runtime: &SmolRuntime, what: &str, done: impl Fn() -> bool) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !done() {
+        assert!(Instant::now() < deadline, "timed out waiting for {what}");
+        runtime.delay_ms(1).await;
+    }
+}
+
+#[test]
+fn pause_resume_cancel() {
+    run(async |runtime| {
+        let ticks = Arc::new(AtomicU32::new(0));
+        let slot: ArcSlot<(), (), (), 4, 4> = ArcSlot::new();
+        let flow = runtime.spawn_flow(tick, ticks.clone(), slot).unwrap();
+        wait_for(&runtime, "the first ticks", || {
+            ticks.load(Ordering::Relaxed) > 2
+        })
+        .await;
+        assert_eq!(flow.state(), FlowState::Running);
+
+        assert!(flow.pause().await.is_ok());
+        assert_eq!(flow.state(), FlowState::Paused);
+        let paused_at = ticks.load(Ordering::Relaxed);
+        runtime.delay_ms(20).await;
+        // the function is not polled while the flow is paused
+        assert_eq!(ticks.load(Ordering::Relaxed), paused_at);
+
+        assert!(flow.resume().await.is_ok());
+        assert_eq!(flow.state(), FlowState::Running);
+        let resumed_at = ticks.load(Ordering::Relaxed);
+        wait_for(&runtime, "ticks after the resume", || {
+            ticks.load(Ordering::Relaxed) > resumed_at + 2
+        })
+        .await;
+
+        assert!(flow.cancel().await.is_ok());
+        assert_eq!(flow.state(), FlowState::Cancelled);
+        assert_eq!(flow.join().await, Ok(FlowOutcome::Cancelled));
+    })
+}
+
+#[test]
+fn join_the_outcome() {
+    run(async |runtime| {
+        let slot: ArcSlot<(), (), (), 4, 4> = ArcSlot::new();
+        let flow = runtime.spawn_flow(sleep, 5, slot).unwrap();
+        assert_eq!(flow.join().await, Ok(FlowOutcome::Completed(5)));
+        assert_eq!(flow.state(), FlowState::Completed);
+    })
+}
+
+#[test]
+fn delay() {
+    run(async |runtime| {
+        let before = runtime.now_us();
+        let started = Instant::now();
+        runtime.delay_ms(10).await;
+        assert!(started.elapsed() >= Duration::from_millis(10));
+        runtime.delay_us(2_000).await;
+        assert!(started.elapsed() >= Duration::from_millis(12));
+        // the clock follows the timers
+        assert!(runtime.now_us() - before >= 12_000);
+    })
+}
@@ -0,0 +1,263 @@
+//! Restarts, give ups and stops of supervisors, on virtual time
+
+use flows_core::runtime::Spawner;
+use flows_core::runtime::sim::SimRuntime;
+use flows_core::{
+    ArcSlot, FlowState, FnController, FnDataHandle, Restart, RestartPolicy, Strategy, Supervisor,
+};
+use std::sync::{Arc, Mutex};
+
+type Slot = ArcSlot<(), (), (), 4, 4>;
+
+/// What a supervised flow does in one run, and where it notes its starts
+#[derive(Clone)]
+struct Job {
+    name: &'static str,
+    /// how long a run takes, `None` runs until it is cancelled
+    after_ms: Option<u32>,
+    fail: bool,
+    log: Log,
+}
+
+/// The virtual times the jobs were started at, by name
+#[derive(Clone)]
+struct Log {
+    sim: SimRuntime,
+    starts: Arc<Mutex<Vec<(&'static str, u64)>>>,
+}
+
+impl Log {
+    fn new(sim: &SimRuntime) -> Self {
+        Self {
+            sim: sim.clone(),
+            starts: Arc::default(),
+        }
+    }
+
+    fn job(&self, name: &'static str, after_ms: Option<u32>, fail: bool) -> Job {
+        Job {
+            name,
+            after_ms,
+            fail,
+            log: self.clone(),
+        }
+    }
+
+    /// start times of `name` in milliseconds
+    fn starts(&self, name: &str) -> Vec<u64> {
+        let starts = self.starts.lock().unwrap();
+        starts
+            .iter()
+            .filter(|(job, _)| *job == name)
+            .map(|(_, at)| at / 1000)
+            .collect()
+    }
+}
+
+async fn run(
+    job: Job,
+    ctrl: FnController<SimRuntime, (), 4>,
+    _data: FnDataHandle<(), (), 4>,
+) -> Result<(), ()> {
+    let now = job.log.sim.time_us();
+    job.log.starts.lock().unwrap().push((job.name, now));
+    match job.after_ms {
+        Some(millis) => ctrl.delay_ms(millis).await,
+        None => loop {
+            ctrl.delay_ms(10).await;
+        },
+    }
+    if job.fail { Err(()) } else { Ok(()) }
+}
+
+fn policy(
+    max_restarts: u32,
+    window_ms: u32,
+    backoff_ms: u32,
+    max_backoff_ms: u32,
+) -> RestartPolicy {
+    RestartPolicy {
+        max_restarts,
+        window_ms,
+        backoff_ms,
+        max_backoff_ms,
+    }
+}
+
+#[test]
+fn backoff_doubles() {
+    let policy = policy(5, 10_000, 10, 100);
+    assert_eq!(
+        (1..=6).map(|nth| policy.backoff(nth)).collect::<Vec<_>>(),
+        [10, 20, 40, 80, 100, 100]
+    );
+
+    let sim = SimRuntime::new();
+    let log = Log::new(&sim);
+    let supervisor = Supervisor::new(sim.clone(), Strategy::OneForOne)
+        .with_policy(policy)
+        .supervise(
+            Restart::OnError,
+            run,
+            log.job("job", Some(0), true),
+            Slot::new(),
+        );
+    // the sixth failure is one restart too many
+    assert_eq!(sim.block_on(supervisor), FlowState::Error);
+    assert_eq!(log.starts("job"), [0, 10, 30, 70, 150, 250]);
+}
+
+#[test]
+fn restarts_spread_out_stay_within_the_window() {
+    let sim = SimRuntime::new();
+    let log = Log::new(&sim);
+    // fails every 60ms, so at most 2 restarts fall within any 100ms
+    let supervisor = Supervisor::new(sim.clone(), Strategy::OneForOne)
+        .with_policy(policy(2, 100, 10, 10))
+        .supervise(
+            Restart::OnError,
+            run,
+            log.job("slow", Some(60), true),
+            Slot::new(),
+        );
+    let stop = supervisor.stop_handle();
+    let ended = Arc::new(Mutex::new(None));
+    let result = ended.clone();
+    sim.spawn(async move { *result.lock().unwrap() = Some(supervisor.await) })
+        .unwrap();
+
+    sim.advance_ms(1_000);
+    assert_eq!(*ended.lock().unwrap(), None);
+    assert_eq!(log.starts("slow").len(), 15);
+
+    stop.stop();
+    sim.run();
+    assert_eq!(*ended.lock().unwrap(), Some(FlowState::Cancelled));
+}
+
+#[test]
+fn restarts_close_together_give_up() {
+    let sim = SimRuntime::new();
+    let log = Log::new(&sim);
+    let supervisor = Supervisor::new(sim.clone(), Strategy::OneForOne)
+        .with_policy(policy(2, 100, 10, 10))
+        .supervise(
+            Restart::OnError,
+            run,
+            log.job("fast", Some(20), true),
+            Slot::new(),
+        );
+    assert_eq!(sim.block_on(supervisor), FlowState::Error);
+    // the third failure at 80ms is the third within 100ms
+    assert_eq!(log.starts("fast"), [0, 30, 60]);
+    assert_eq!(sim.time_us(), 80_000);
+}
+
+#[test]
+fn one_for_all_restarts_what_its_policy_allows() {
+    let sim = SimRuntime::new();
+    let log = Log::new(&sim);
+    let done = Slot::new();
+    let ticking = Slot::new();
+    let supervisor = Supervisor::new(sim.clone(), Strategy::OneForAll)
+        .with_policy(policy(1, 10_000, 10, 10))
+        // completes at once, nothing restarts it
+        .supervise(
+            Restart::OnError,
+            run,
+            log.job("done", Some(0), false),
+            done.clone(),
+        )
+        .supervise(
+            Restart::OnError,
+            run,
+            log.job("failing", Some(30), true),
+            Slot::new(),
+        )
+        .supervise(
+            Restart::Always,
+            run,
+            log.job("ticking", None, false),
+            ticking.clone(),
+        )
+        .supervise(
+            Restart::Never,
+            run,
+            log.job("once", None, false),
+            Slot::new(),
+        );
+    // the second failure gives up
+    assert_eq!(sim.block_on(supervisor), FlowState::Error);
+
+    assert_eq!(log.starts("done"), [0]);
+    assert_eq!(done.user().state(), FlowState::Completed);
+    // restarted together once the first run of `failing` ended
+    assert_eq!(log.starts("failing"), [0, 40]);
+    assert_eq!(log.starts("ticking"), [0, 40]);
+    assert_eq!(log.starts("once"), [0]);
+    assert_eq!(ticking.user().state(), FlowState::Cancelled);
+}
+
+#[test]
+fn nested_supervisors() {
+    let sim = SimRuntime::new();
+    let log = Log::new(&sim);
+    let inner = Supervisor::new(sim.clone(), Strategy::OneForOne)
+        .with_policy(policy(1, 10_000, 10, 10))
+        .supervise(
+            Restart::OnError,
+            run,
+            log.job("inner", Some(0), true),
+            Slot::new(),
+        );
+    let outer = Supervisor::new(sim.clone(), Strategy::OneForOne)
+        .with_policy(policy(1, 10_000, 100, 100))
+        .supervise(
+            Restart::Never,
+            run,
+            log.job("outer", Some(1_000), false),
+            Slot::new(),
+        )
+        .with_child(inner);
+    // the inner supervisor gives up twice, the outer one restarts it once
+    assert_eq!(sim.block_on(outer), FlowState::Error);
+    assert_eq!(log.starts("inner"), [0, 10, 110, 120]);
+    assert_eq!(log.starts("outer"), [0]);
+}
+
+#[test]
+fn stop_handle_cancels_the_children() {
+    let sim = SimRuntime::new();
+    let log = Log::new(&sim);
+    let slot = Slot::new();
+    let inner = Supervisor::new(sim.clone(), Strategy::OneForOne).supervise(
+        Restart::Always,
+        run,
+        log.job("nested", None, false),
+        Slot::new(),
+    );
+    let root = Supervisor::new(sim.clone(), Strategy::OneForOne)
+        .supervise(
+            Restart::Always,
+            run,
+            log.job("root", None, false),
+            slot.clone(),
+        )
+        .with_child(inner);
+    let stop = root.stop_handle();
+    let ended = Arc::new(Mutex::new(None));
+    let result = ended.clone();
+    sim.spawn(async move { *result.lock().unwrap() = Some(root.await) })
+        .unwrap();
+
+    sim.advance_ms(100);
+    assert_eq!(slot.user().state(), FlowState::Running);
+
+    stop.stop();
+    sim.run();
+    assert_eq!(*ended.lock().unwrap(), Some(FlowState::Cancelled));
+    assert_eq!(slot.user().state(), FlowState::Cancelled);
+    // nothing was restarted
+    assert_eq!(log.starts("root"), [0]);
+    assert_eq!(log.starts("nested"), [0]);
+}
@@ -0,0 +1,134 @@
+//! Spawns flows and plain futures on the std thread runtime
+
+use flows_core::runtime::thread::ThreadRuntime;
+use flows_core::runtime::{FlowRuntime, Spawner, Timer};
+use flows_core::{ArcSlot, FlowOutcome, FlowState, FnController, FnDataHandle, JoinError};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+type Ctrl = FnController<ThreadRuntime, (), 4>;
+type Data = FnDataHandle<(), u32, 4>;
+
+async fn double(n: u32, ctrl: Ctrl, data: Data) -> Result<u32, ()> {
+    ctrl.delay_ms(1).await;
+    let _ = data.try_push(n);
+    Ok(n * 2)
+}
+
+async fn tick(ticks: Arc<AtomicU32>, ctrl: Ctrl, _data: Data) -> Result<(), ()> {
+    loop {
+        ticks.fetch_add(1, Ordering::Relaxed);
+        ctrl.delay_ms(1).await;
+    }
+}
+
+async fn explode(_init: (), ctrl: Ctrl, _data: Data) -> Result<(), ()> {
+    ctrl.delay_ms(1).await;
+    panic!("the flow blew up");
+}
+
+fn wait_for(what: &str, done: impl Fn() -> bool) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !done() {
+        assert!(Instant::now() < deadline, "timed out waiting for {what}");
+        thread::sleep(Duration::from_millis(1));
+    }
+}
+
+#[test]
+fn spawn_and_join() {
+    let runtime = ThreadRuntime::new(2);
+    let slot: ArcSlot<(), (), u32, 4, 4> = ArcSlot::new();
+    let mut flow = runtime.spawn_flow(double, 21, slot).unwrap();
+    assert_eq!(
+        runtime.block_on(flow.join()),
+        Ok(FlowOutcome::Completed(42))
+    );
+    assert_eq!(flow.state(), FlowState::Completed);
+    assert_eq!(flow.data().try_recv(), Some(21));
+    wait_for("the task to finish", || runtime.tasks() == 0);
+
+    // plain futures run on the workers as well
+    let (sender, received) = mpsc::channel();
+    runtime
+        .spawn(async move { sender.send(7).unwrap() })
+        .unwrap();
+    assert_eq!(received.recv_timeout(Duration::from_secs(5)), Ok(7));
+}
+
+#[test]
+fn delay() {
+    let runtime = ThreadRuntime::new(1);
+    let start = Instant::now();
+    runtime.block_on(runtime.delay_ms(20));
+    assert!(start.elapsed() >= Duration::from_millis(20));
+
+    // due in the order of their deadlines, not of their registration
+    let order = Arc::new(std::sync::Mutex::new(Vec::new()));
+    for millis in [30, 10, 20] {
+        let (order, delay) = (order.clone(), runtime.delay_ms(millis));
+        runtime
+            .spawn(async move {
+                delay.await;
+                order.lock().unwrap().push(millis);
+            })
+            .unwrap();
+    }
+    wait_for("the delays", || order.lock().unwrap().len() == 3);
+    assert_eq!(*order.lock().unwrap(), [10, 20, 30]);
+}
+
+#[test]
+fn block_on() {
+    let runtime = ThreadRuntime::new(1);
+    assert_eq!(runtime.block_on(async { 1 + 1 }), 2);
+
+    // woken from a worker thread
+    let (sender, received) = mpsc::channel();
+    let delay = runtime.delay_ms(5);
+    runtime
+        .spawn(async move {
+            delay.await;
+            sender.send(()).unwrap()
+        })
+        .unwrap();
+    let waited = runtime.block_on(async {
+        runtime.delay_ms(10).await;
+        received.try_recv().is_ok()
+    });
+    assert!(waited);
+}
+
+#[test]
+fn panic_ends_the_join() {
+    let runtime = ThreadRuntime::new(1);
+    let slot: ArcSlot<(), (), u32, 4, 4> = ArcSlot::new();
+    let flow = runtime.spawn_flow(explode, (), slot).unwrap();
+    assert_eq!(runtime.block_on(flow.join()), Err(JoinError::Panicked));
+    wait_for("the task to be dropped", || runtime.tasks() == 0);
+
+    // the worker keeps going
+    let slot: ArcSlot<(), (), u32, 4, 4> = ArcSlot::new();
+    let flow = runtime.spawn_flow(double, 1, slot).unwrap();
+    assert_eq!(runtime.block_on(flow.join()), Ok(FlowOutcome::Completed(2)));
+}
+
+#[test]
+fn shutdown_drops_flows_that_never_end() {
+    let runtime = ThreadRuntime::new(2);
+    let ticks = Arc::new(AtomicU32::new(0));
+    let slot: ArcSlot<(), (), u32, 4, 4> = ArcSlot::new();
+    let flow = runtime.spawn_flow(tick, ticks.clone(), slot).unwrap();
+    wait_for("the first ticks", || ticks.load(Ordering::Relaxed) > 2);
+
+    runtime.shutdown();
+    assert_eq!(runtime.tasks(), 0);
+    // the flow and what it held are gone
+    assert_eq!(Arc::strong_count(&ticks), 1);
+    // `block_on` runs on this thread, it works after the shutdown
+    assert_eq!(runtime.block_on(flow.join()), Err(JoinError::Dropped));
+    assert!(runtime.spawn(async {}).is_err());
+}
@@ -0,0 +1,86 @@
+//! Drives flows whose functions are not `Send` on a tokio `LocalSet`
+
+use flows_core::runtime::FlowRuntime;
+use flows_core::runtime::tokio::TokioRuntime;
+use flows_core::{ArcSlot, FlowOutcome, FlowState, FnController, FnDataHandle};
+use std::cell::Cell;
+use std::rc::Rc;
+use std::time::Duration;
+use tokio::task::LocalSet;
+
+type Slot = ArcSlot<(), (), u32, 4, 4>;
+
+/// Pushes a tick every millisecond, counted in an `Rc` held across the delays
+async fn tick(
+    _init: (),
+    ctrl: FnController<TokioRuntime, (), 4>,
+    data: FnDataHandle<(), u32, 4>,
+) -> Result<(), ()> {
+    let ticks = Rc::new(Cell::new(0));
+    loop {
+        ticks.set(ticks.get() + 1);
+        let _ = data.try_push(ticks.get());
+        ctrl.delay_ms(1).await;
+    }
+}
+
+/// Completes with `millis` once they passed, counting them in an `Rc`
+async fn sleep(
+    millis: u32,
+    ctrl: FnController<TokioRuntime, (), 4>,
+    _data: FnDataHandle<(), u32, 4>,
+) -> Result<u32, ()> {
+    let slept = Rc::new(Cell::new(0));
+    ctrl.delay_ms(millis).await;
+    slept.set(millis);
+    Ok(slept.get())
+}
+
+#[tokio::test]
+async fn spawn_on_the_local_set() {
+    let runtime = TokioRuntime::new();
+    let local = LocalSet::new();
+    let mut flow = runtime
+        .spawn_local_flow(&local, tick, (), Slot::new())
+        .unwrap();
+
+    local
+        .run_until(async {
+            while flow.data().try_recv().is_none() {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+            assert!(flow.pause().await.is_ok());
+            assert_eq!(flow.state(), FlowState::Paused);
+            while flow.data().try_recv().is_some() {}
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            // the function is not polled while the flow is paused
+            assert_eq!(flow.data().try_recv(), None);
+
+            assert!(flow.resume().await.is_ok());
+            assert_eq!(flow.state(), FlowState::Running);
+            assert!(flow.cancel().await.is_ok());
+            assert_eq!(flow.join().await, Ok(FlowOutcome::Cancelled));
+        })
+        .await;
+}
+
+#[tokio::test]
+async fn spawn_from_within_the_local_set() {
+    let runtime = TokioRuntime::new();
+    LocalSet::new()
+        .run_until(async {
+            let flow = runtime
+                .spawn_local_flow(&runtime, sleep, 5, Slot::new())
+                .unwrap();
+            assert_eq!(flow.join().await, Ok(FlowOutcome::Completed(5)));
+            assert_eq!(flow.state(), FlowState::Completed);
+        })
+        .await;
+}
+
+#[tokio::test]
+#[should_panic]
+async fn spawn_outside_a_local_set_panics() {
+    let runtime = TokioRuntime::new();
+    let _ = runtime.spawn_local_flow(&runtime, sleep, 5, Slot::new());
+}
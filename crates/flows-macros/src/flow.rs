@@ -0,0 +1,183 @@
+use proc_macro2::{Span, TokenStream};
+use quote::{ToTokens, quote};
+use syn::parse::{Parse, ParseStream};
+use syn::punctuated::Punctuated;
+use syn::spanned::Spanned;
+use syn::{
+    Error, Expr, FnArg, GenericArgument, ItemFn, PathArguments, Result, ReturnType, Token, Type,
+    parse_quote,
+};
+
+/// `chan = N, data = N`
+pub struct Args {
+    chan: Expr,
+    data: Expr,
+}
+
+impl Parse for Args {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let mut chan = None;
+        let mut data = None;
+        for arg in Punctuated::<syn::MetaNameValue, Token![,]>::parse_terminated(input)? {
+            let name = arg.path.require_ident()?;
+            let slot = match name.to_string().as_str() {
+                "chan" => &mut chan,
+                "data" => &mut data,
+                _ => return Err(Error::new(name.span(), "expected `chan` or `data`")),
+            };
+            if slot.replace(arg.value).is_some() {
+                return Err(Error::new(name.span(), format!("`{}` given twice", name)));
+            }
+        }
+        Ok(Args {
+            chan: chan.ok_or_else(|| Error::new(Span::call_site(), "missing `chan = <size>`"))?,
+            data: data.ok_or_else(|| Error::new(Span::call_site(), "missing `data = <size>`"))?,
+        })
+    }
+}
+
+pub fn expand(args: Args, mut function: ItemFn) -> Result<TokenStream> {
+    if function.sig.asyncness.is_none() {
+        return Err(Error::new(
+            function.sig.fn_token.span,
+            "a flow must be an `async fn`",
+        ));
+    }
+    if !function.sig.generics.params.is_empty() {
+        return Err(Error::new(
+            function.sig.generics.span(),
+            "a flow cannot be generic",
+        ));
+    }
+    if function.sig.inputs.len() != 3 {
+        return Err(Error::new(
+            function.sig.inputs.span(),
+            "a flow takes `(init, ctrl: FnController<..>, data: FnDataHandle<..>)`",
+        ));
+    }
+
+    let chan: GenericArgument = {
+        let chan = &args.chan;
+        parse_quote!({ #chan })
+    };
+    let data: GenericArgument = {
+        let data = &args.data;
+        parse_quote!({ #data })
+    };
+
+    let mut inputs = function.sig.inputs.iter_mut().map(|input| match input {
+        FnArg::Typed(typed) => Ok(&mut *typed.ty),
+        FnArg::Receiver(receiver) => Err(Error::new(receiver.span(), "a flow cannot take `self`")),
+    });
+    let init_ty = inputs.next().unwrap()?.clone();
+    let ctrl_args = fill_size(inputs.next().unwrap()?, "FnController", 2, chan.clone())?;
+    let data_args = fill_size(inputs.next().unwrap()?, "FnDataHandle", 2, data.clone())?;
+
+    let runtime = &ctrl_args[0];
+    let u = &ctrl_args[1];
+    let q = optional(&ctrl_args, 3, quote!(()));
+    let st = optional(&ctrl_args, 4, quote!(::flows::FlowState));
+    let h = optional(&ctrl_args, 5, quote!(::flows::FlowEventHandler));
+    let journal = optional(&ctrl_args, 6, quote!({ ::flows::JOURNAL_CAPACITY }));
+    let ud = &data_args[0];
+    let fd = &data_args[1];
+
+    let output = match &function.sig.output {
+        ReturnType::Default => quote!(()),
+        ReturnType::Type(_, ty) => ty.to_token_stream(),
+    };
+
+    let slot_ty = quote!(::flows::Slot<#u, #ud, #fd, #chan, #data, #q, #st, #h, #journal>);
+    // a custom handler is only known to be `Default`, so its slot is built on first use
+    let slot = if ctrl_args.len() > 5 {
+        quote! {
+            static SLOT: ::std::sync::LazyLock<#slot_ty> =
+                ::std::sync::LazyLock::new(::core::default::Default::default);
+        }
+    } else {
+        quote!(static SLOT: #slot_ty = ::flows::Slot::new();)
+    };
+
+    let vis = &function.vis;
+    let name = &function.sig.ident;
+    let doc = format!("Launcher for the [`{}`] flow", name);
+
+    Ok(quote! {
+        #function
+
+        #[doc = #doc]
+        #vis mod #name {
+            #[allow(unused_imports)]
+            use super::*;
+
+            /// How a run of the flow ends
+            pub type Outcome = ::flows::FlowOutcome<
+                <#output as ::flows::FlowOutput>::Value,
+                <#output as ::flows::FlowOutput>::Error,
+            >;
+
+            /// What `launch` hands back
+            pub type Handle = ::flows::Launched<
+                Outcome, #u, #ud, #fd, #chan, #data, #q, #st, #h,
+                <#runtime as ::flows::runtime::Spawner>::Handle, #journal,
+            >;
+
+            #slot
+
+            static JOIN: ::flows::JoinCell<Outcome> = ::flows::JoinCell::new();
+
+            /// Spawn the flow on `runtime`, one run at a time
+            pub fn launch(
+                runtime: &#runtime,
+                init: #init_ty,
+            ) -> ::core::result::Result<
+                Handle,
+                ::flows::LaunchError<<#runtime as ::flows::runtime::Spawner>::Error>,
+            > {
+                let slot: &'static #slot_ty = &SLOT;
+                ::flows::runtime::FlowRuntime::launch_flow(runtime, super::#name, init, slot, &JOIN)
+            }
+        }
+    })
+}
+
+/// Generic arguments of `ty`, with the size at `index` filled in if left out or written as `_`
+fn fill_size(
+    ty: &mut Type,
+    expected: &str,
+    index: usize,
+    size: GenericArgument,
+) -> Result<Vec<GenericArgument>> {
+    let span = ty.span();
+    let error = || {
+        Error::new(
+            span,
+            format!("expected `{}<..>` with its generic arguments", expected),
+        )
+    };
+    let Type::Path(path) = &mut *ty else {
+        return Err(error());
+    };
+    let segment = path.path.segments.last_mut().ok_or_else(error)?;
+    if segment.ident != expected {
+        return Err(error());
+    }
+    let PathArguments::AngleBracketed(args) = &mut segment.arguments else {
+        return Err(error());
+    };
+    if args.args.len() < index {
+        return Err(error());
+    }
+    if args.args.len() == index {
+        args.args.push(size);
+    } else if matches!(&args.args[index], GenericArgument::Type(Type::Infer(_))) {
+        args.args[index] = size;
+    }
+    Ok(args.args.iter().cloned().collect())
+}
+
+fn optional(args: &[GenericArgument], index: usize, default: TokenStream) -> TokenStream {
+    args.get(index)
+        .map(ToTokens::to_token_stream)
+        .unwrap_or(default)
+}
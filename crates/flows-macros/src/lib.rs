@@ -0,0 +1,93 @@
+use proc_macro::TokenStream;
+use syn::parse_macro_input;
+
+mod flow;
+mod state_machine;
+
+/// Generate a flow state machine from a transition table
+///
+/// ```ignore
+/// flows::state_machine! {
+///     pub Throttle {
+///         // the first state is the initial one, every state behaves like one of the `FlowState`s
+///         states {
+///             Running: Running,
+///             Throttled: Paused,
+///             Blocked: Blocked,
+///             Cancelled: Cancelled,
+///         }
+///         // named events sent with `signal`, the name defaults to the variant name
+///         signals {
+///             Slow = "slow",
+///             Fast = "fast",
+///         }
+///         transitions {
+///             Running + Slow => Throttled,
+///             Throttled + Fast => Running,
+///             Running + Block => Blocked,
+///             Blocked + Invoke => Running,
+///             Blocked + Answer => Running,
+///             _ + Cancel => Cancelled,
+///         }
+///     }
+/// }
+/// ```
+///
+/// This expands to a `ThrottleState` enum implementing `State`, a `ThrottleEvent` enum of the
+/// built-in events and the signals, and a `Throttle` handler implementing `Handler`.
+/// The function is polled in states of kind `Running`, answers to queries are released in any
+/// non-terminal state. `Completed`, `Cancelled` and `Error` states are added if not declared.
+/// A flow is shown in its first `Blocked` state while one of its child flows is blocked, a
+/// machine that declares none is not shown as blocked.
+///
+/// `_` applies a transition to every non-terminal state without its own entry for the event.
+/// Transitions out of terminal states, duplicate transitions and states that can never be
+/// reached from the initial state are compile errors.
+///
+/// The states are plain variants, packed into a byte for lock-free publishing. States that carry
+/// data, such as `WaitingForApproval(reason)`, need a hand-written `State` and `Handler`.
+#[proc_macro]
+pub fn state_machine(input: TokenStream) -> TokenStream {
+    let machine = parse_macro_input!(input as state_machine::Machine);
+    machine
+        .expand()
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+/// Turn an `async fn(init, ctrl, data)` into a flow with its own slot and launcher
+///
+/// ```ignore
+/// #[flows::flow(chan = 8, data = 16)]
+/// async fn example(
+///     init: u32,
+///     ctrl: flows::FnController<TokioRuntime, ()>,
+///     data: flows::FnDataHandle<(), u32>,
+/// ) -> anyhow::Result<()> {
+///     // ...
+/// }
+///
+/// let flow = example::launch(&runtime, 42)?;
+/// flow.ctrl.pause().await?;
+/// let outcome = flow.join.await?;
+/// ```
+///
+/// The channel sizes are filled into the `FnController` and `FnDataHandle` types, either left
+/// out or written as `_`. The function stays as it is, next to it a module of the same name
+/// holds the slot and `launch`, which spawns the flow and returns a `flows::Launched` with the
+/// user controller, the user data handle and a future joining the `FlowOutcome`.
+///
+/// The function returns `()`, a `Result`, or any other value wrapped in `flows::Done`.
+///
+/// Each function has a single static slot, so only one run can be live at a time, `launch` fails
+/// with `LaunchError::Busy` meanwhile. Once it is launched again, the join of an earlier run
+/// resolves to `JoinError::Replaced`. The slot needs no heap, unless the `FnController` names a
+/// handler of its own: such a slot is built on the first launch, which requires `std`.
+#[proc_macro_attribute]
+pub fn flow(args: TokenStream, input: TokenStream) -> TokenStream {
+    let args = parse_macro_input!(args as flow::Args);
+    let function = parse_macro_input!(input as syn::ItemFn);
+    flow::expand(args, function)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
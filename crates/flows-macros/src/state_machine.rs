@@ -0,0 +1,512 @@
+use proc_macro2::{Span, TokenStream};
+use quote::{format_ident, quote};
+use std::collections::{BTreeMap, VecDeque};
+use syn::parse::{Parse, ParseStream};
+use syn::{Attribute, Error, Ident, LitStr, Result, Token, Visibility, braced};
+
+/// the `FlowState`s a declared state can behave like
+const KINDS: [&str; 6] = [
+    "Running",
+    "Paused",
+    "Blocked",
+    "Cancelled",
+    "Completed",
+    "Error",
+];
+const TERMINAL_KINDS: [&str; 3] = ["Completed", "Cancelled", "Error"];
+/// events every machine understands, in addition to its signals
+const BUILTIN_EVENTS: [&str; 6] = ["Pause", "Resume", "Cancel", "Invoke", "Answer", "Block"];
+
+pub struct Machine {
+    attrs: Vec<Attribute>,
+    vis: Visibility,
+    name: Ident,
+    states: Vec<StateDecl>,
+    signals: Vec<SignalDecl>,
+    transitions: Vec<TransitionDecl>,
+}
+
+struct StateDecl {
+    name: Ident,
+    kind: Ident,
+    /// added because the table did not declare a state of this terminal kind
+    implicit: bool,
+}
+
+struct SignalDecl {
+    name: Ident,
+    signal: LitStr,
+}
+
+struct TransitionDecl {
+    /// `None` for `_`, which applies to every non-terminal state
+    from: Option<Ident>,
+    from_span: Span,
+    event: Ident,
+    to: Ident,
+}
+
+impl Parse for Machine {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let attrs = input.call(Attribute::parse_outer)?;
+        let vis = input.parse()?;
+        let name: Ident = input.parse()?;
+
+        let body;
+        braced!(body in input);
+
+        let mut states = None;
+        let mut signals = None;
+        let mut transitions = None;
+        while !body.is_empty() {
+            let section: Ident = body.parse()?;
+            let content;
+            braced!(content in body);
+            match section.to_string().as_str() {
+                "states" if states.is_none() => {
+                    states = Some(parse_list(&content, |input| {
+                        let name = input.parse()?;
+                        input.parse::<Token![:]>()?;
+                        let kind = input.parse()?;
+                        Ok(StateDecl {
+                            name,
+                            kind,
+                            implicit: false,
+                        })
+                    })?)
+                }
+                "signals" if signals.is_none() => {
+                    signals = Some(parse_list(&content, |input| {
+                        let name: Ident = input.parse()?;
+                        let signal = if input.peek(Token![=]) {
+                            input.parse::<Token![=]>()?;
+                            input.parse()?
+                        } else {
+                            LitStr::new(&name.to_string(), name.span())
+                        };
+                        Ok(SignalDecl { name, signal })
+                    })?)
+                }
+                "transitions" if transitions.is_none() => {
+                    transitions = Some(parse_list(&content, |input| {
+                        let (from, from_span) = if input.peek(Token![_]) {
+                            (None, input.parse::<Token![_]>()?.span)
+                        } else {
+                            let from: Ident = input.parse()?;
+                            let span = from.span();
+                            (Some(from), span)
+                        };
+                        input.parse::<Token![+]>()?;
+                        let event = input.parse()?;
+                        input.parse::<Token![=>]>()?;
+                        let to = input.parse()?;
+                        Ok(TransitionDecl {
+                            from,
+                            from_span,
+                            event,
+                            to,
+                        })
+                    })?)
+                }
+                "states" | "signals" | "transitions" => {
+                    return Err(Error::new(section.span(), "section declared twice"));
+                }
+                _ => {
+                    return Err(Error::new(
+                        section.span(),
+                        "expected `states`, `signals` or `transitions`",
+                    ));
+                }
+            }
+        }
+
+        let states = states.ok_or_else(|| Error::new(name.span(), "missing `states` section"))?;
+        let transitions =
+            transitions.ok_or_else(|| Error::new(name.span(), "missing `transitions` section"))?;
+        if states.is_empty() {
+            return Err(Error::new(
+                name.span(),
+                "a machine needs at least one state",
+            ));
+        }
+
+        Ok(Machine {
+            attrs,
+            vis,
+            name,
+            states,
+            signals: signals.unwrap_or_default(),
+            transitions,
+        })
+    }
+}
+
+/// comma separated entries, trailing comma allowed
+fn parse_list<T>(input: ParseStream, entry: fn(ParseStream) -> Result<T>) -> Result<Vec<T>> {
+    let mut entries = Vec::new();
+    while !input.is_empty() {
+        entries.push(entry(input)?);
+        if input.is_empty() {
+            break;
+        }
+        input.parse::<Token![,]>()?;
+    }
+    Ok(entries)
+}
+
+impl Machine {
+    pub fn expand(mut self) -> Result<TokenStream> {
+        self.check_states()?;
+        let events = self.events()?;
+        let table = self.table(&events)?;
+        self.check_reachable(&table)?;
+        Ok(self.generate(&events, &table))
+    }
+
+    fn state_index(&self, name: &Ident) -> Result<usize> {
+        self.states
+            .iter()
+            .position(|s| s.name == *name)
+            .ok_or_else(|| Error::new(name.span(), format!("unknown state `{}`", name)))
+    }
+
+    fn is_terminal(&self, index: usize) -> bool {
+        TERMINAL_KINDS.contains(&self.states[index].kind.to_string().as_str())
+    }
+
+    /// the state `State::from_kind` maps a terminal kind to, they always have one
+    fn first_of_kind(&self, kind: &str) -> usize {
+        self.states
+            .iter()
+            .position(|s| s.kind == kind)
+            .expect("every terminal kind has a state")
+    }
+
+    fn check_states(&mut self) -> Result<()> {
+        for (i, state) in self.states.iter().enumerate() {
+            if !KINDS.contains(&state.kind.to_string().as_str()) {
+                return Err(Error::new(
+                    state.kind.span(),
+                    format!(
+                        "unknown state kind `{}`, expected one of the `FlowState` variants",
+                        state.kind
+                    ),
+                ));
+            }
+            if self.states[..i].iter().any(|s| s.name == state.name) {
+                return Err(Error::new(
+                    state.name.span(),
+                    format!("state `{}` declared twice", state.name),
+                ));
+            }
+        }
+        if self.is_terminal(0) {
+            return Err(Error::new(
+                self.states[0].name.span(),
+                "the initial state cannot be terminal",
+            ));
+        }
+
+        // the flow enters these on its own when the function finishes, fails or is cancelled
+        for kind in TERMINAL_KINDS {
+            if self.states.iter().any(|s| s.kind == kind) {
+                continue;
+            }
+            if let Some(taken) = self.states.iter().find(|s| s.name == kind) {
+                return Err(Error::new(
+                    taken.name.span(),
+                    format!(
+                        "`{}` is needed for the implicit {} state, declare a state of kind {}",
+                        kind, kind, kind
+                    ),
+                ));
+            }
+            self.states.push(StateDecl {
+                name: Ident::new(kind, Span::call_site()),
+                kind: Ident::new(kind, Span::call_site()),
+                implicit: true,
+            });
+        }
+
+        if self.states.len() > u8::MAX as usize {
+            return Err(Error::new(self.name.span(), "too many states"));
+        }
+        Ok(())
+    }
+
+    /// built-in events followed by the signals
+    fn events(&self) -> Result<Vec<Ident>> {
+        let mut events: Vec<Ident> = BUILTIN_EVENTS
+            .iter()
+            .map(|e| Ident::new(e, Span::call_site()))
+            .collect();
+        for (i, signal) in self.signals.iter().enumerate() {
+            if events.contains(&signal.name) {
+                return Err(Error::new(
+                    signal.name.span(),
+                    format!("event `{}` declared twice", signal.name),
+                ));
+            }
+            if self.signals[..i]
+                .iter()
+                .any(|s| s.signal.value() == signal.signal.value())
+            {
+                return Err(Error::new(
+                    signal.signal.span(),
+                    format!("signal \"{}\" declared twice", signal.signal.value()),
+                ));
+            }
+            events.push(signal.name.clone());
+        }
+        Ok(events)
+    }
+
+    /// (from, event) -> to, with `_` expanded
+    fn table(&self, events: &[Ident]) -> Result<BTreeMap<(usize, usize), usize>> {
+        let event_index = |event: &Ident| {
+            events
+                .iter()
+                .position(|e| e == event)
+                .ok_or_else(|| Error::new(event.span(), format!("unknown event `{}`", event)))
+        };
+
+        let mut table = BTreeMap::new();
+        let mut wildcards = BTreeMap::new();
+        for transition in &self.transitions {
+            let event = event_index(&transition.event)?;
+            let to = self.state_index(&transition.to)?;
+            match &transition.from {
+                Some(from_name) => {
+                    let from = self.state_index(from_name)?;
+                    if self.is_terminal(from) {
+                        return Err(Error::new(
+                            transition.from_span,
+                            format!("`{}` is terminal and cannot transition", from_name),
+                        ));
+                    }
+                    if table.insert((from, event), to).is_some() {
+                        return Err(Error::new(
+                            transition.from_span,
+                            format!(
+                                "duplicate transition for `{} + {}`",
+                                from_name, transition.event
+                            ),
+                        ));
+                    }
+                }
+                None => {
+                    if wildcards.insert(event, to).is_some() {
+                        return Err(Error::new(
+                            transition.from_span,
+                            format!("duplicate transition for `_ + {}`", transition.event),
+                        ));
+                    }
+                }
+            }
+        }
+
+        for (event, to) in wildcards {
+            for from in 0..self.states.len() {
+                if !self.is_terminal(from) {
+                    table.entry((from, event)).or_insert(to);
+                }
+            }
+        }
+        Ok(table)
+    }
+
+    fn check_reachable(&self, table: &BTreeMap<(usize, usize), usize>) -> Result<()> {
+        let mut reached = vec![false; self.states.len()];
+        // the initial state, and the states the flow enters when the function finishes
+        let mut queue: VecDeque<usize> = [
+            0,
+            self.first_of_kind("Completed"),
+            self.first_of_kind("Error"),
+        ]
+        .into();
+        while let Some(state) = queue.pop_front() {
+            if std::mem::replace(&mut reached[state], true) {
+                continue;
+            }
+            queue.extend(
+                table
+                    .iter()
+                    .filter(|((from, _), _)| *from == state)
+                    .map(|(_, to)| *to),
+            );
+        }
+
+        match self
+            .states
+            .iter()
+            .zip(&reached)
+            .find(|(state, reached)| !**reached && !state.implicit)
+        {
+            Some((state, _)) => Err(Error::new(
+                state.name.span(),
+                format!(
+                    "state `{}` is unreachable from `{}`",
+                    state.name, self.states[0].name
+                ),
+            )),
+            None => Ok(()),
+        }
+    }
+
+    fn generate(&self, events: &[Ident], table: &BTreeMap<(usize, usize), usize>) -> TokenStream {
+        let Machine {
+            attrs, vis, name, ..
+        } = self;
+        let state_ty = format_ident!("{}State", name);
+        let event_ty = format_ident!("{}Event", name);
+
+        let state_names: Vec<&Ident> = self.states.iter().map(|s| &s.name).collect();
+        let state_kinds: Vec<&Ident> = self.states.iter().map(|s| &s.kind).collect();
+        let state_bits: Vec<u8> = (0..self.states.len() as u8).collect();
+        let (initial, rest) = (state_names[0], &state_names[1..]);
+        // the first state declared of each kind, kinds without one map to the initial state
+        let (kinds, of_kind): (Vec<Ident>, Vec<&Ident>) = KINDS
+            .iter()
+            .filter_map(|kind| {
+                let state = self.states.iter().position(|s| s.kind == kind)?;
+                Some((Ident::new(kind, Span::call_site()), state_names[state]))
+            })
+            .unzip();
+        let other_kinds = (kinds.len() < KINDS.len()).then(|| quote!(_ => Self::#initial,));
+
+        let signal_names: Vec<&Ident> = self.signals.iter().map(|s| &s.name).collect();
+        let signal_strs: Vec<&LitStr> = self.signals.iter().map(|s| &s.signal).collect();
+
+        let (froms, (evs, tos)): (Vec<&Ident>, (Vec<&Ident>, Vec<&Ident>)) = table
+            .iter()
+            .map(|((from, event), to)| (state_names[*from], (&events[*event], state_names[*to])))
+            .unzip();
+
+        quote! {
+            #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+            #vis enum #state_ty {
+                #[default]
+                #initial,
+                #( #rest, )*
+            }
+
+            #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+            #vis enum #event_ty {
+                #( #events, )*
+            }
+
+            impl #event_ty {
+                /// The event of this machine a flow event stands for, `None` for unknown signals
+                pub fn of<U>(event: &::flows::FlowEvent<U>) -> ::core::option::Option<Self> {
+                    use ::core::option::Option::{None, Some};
+                    use ::flows::{FlowEvent, FnControlEvent, UserControlEvent};
+                    match event {
+                        FlowEvent::User(UserControlEvent::Pause) => Some(Self::Pause),
+                        FlowEvent::User(UserControlEvent::Resume) => Some(Self::Resume),
+                        FlowEvent::User(UserControlEvent::Cancel) => Some(Self::Cancel),
+                        FlowEvent::User(UserControlEvent::Invoke(_)) => Some(Self::Invoke),
+                        FlowEvent::User(UserControlEvent::Answer(..)) => Some(Self::Answer),
+                        FlowEvent::Fn(FnControlEvent::Block) => Some(Self::Block),
+                        FlowEvent::User(UserControlEvent::Signal(signal))
+                        | FlowEvent::Fn(FnControlEvent::Signal(signal)) => match *signal {
+                            #( #signal_strs => Some(Self::#signal_names), )*
+                            _ => None,
+                        },
+                    }
+                }
+            }
+
+            impl ::flows::State for #state_ty {
+                fn kind(&self) -> ::flows::FlowState {
+                    match self {
+                        #( Self::#state_names => ::flows::FlowState::#state_kinds, )*
+                    }
+                }
+
+                fn from_kind(kind: ::flows::FlowState) -> Self {
+                    match kind {
+                        #( ::flows::FlowState::#kinds => Self::#of_kind, )*
+                        #other_kinds
+                    }
+                }
+
+                fn to_bits(&self) -> ::core::option::Option<u8> {
+                    ::core::option::Option::Some(*self as u8)
+                }
+
+                fn from_bits(bits: u8) -> ::core::option::Option<Self> {
+                    match bits {
+                        #( #state_bits => ::core::option::Option::Some(Self::#state_names), )*
+                        _ => ::core::option::Option::None,
+                    }
+                }
+            }
+
+            #( #attrs )*
+            #[derive(Debug, Clone, Copy, Default)]
+            #vis struct #name;
+
+            impl #name {
+                /// Every transition of the machine, with `_` expanded
+                pub const TRANSITIONS: &'static [(#state_ty, #event_ty, #state_ty)] = &[
+                    #( (#state_ty::#froms, #event_ty::#evs, #state_ty::#tos), )*
+                ];
+
+                /// The state `event` leads to from `current`, `current` itself if it has no transition
+                pub fn next(current: #state_ty, event: #event_ty) -> #state_ty {
+                    match (current, event) {
+                        #( (#state_ty::#froms, #event_ty::#evs) => #state_ty::#tos, )*
+                        _ => current,
+                    }
+                }
+            }
+
+            impl<U> ::flows::Handler<#state_ty, ::flows::FlowEvent<U>> for #name {
+                type Output = ::flows::Reply<U>;
+
+                fn transition(
+                    &self,
+                    current: &#state_ty,
+                    event: &::flows::FlowEvent<U>,
+                ) -> #state_ty {
+                    match #event_ty::of(event) {
+                        ::core::option::Option::Some(event) => Self::next(*current, event),
+                        ::core::option::Option::None => *current,
+                    }
+                }
+
+                fn transient_exec(
+                    &self,
+                    _previous: &#state_ty,
+                    state: &#state_ty,
+                    event: ::flows::FlowEvent<U>,
+                ) -> ::core::option::Option<::flows::Reply<U>> {
+                    use ::core::option::Option::{None, Some};
+                    use ::flows::{FlowEvent, Reply, UserControlEvent};
+                    if ::flows::State::is_terminal(state) {
+                        return None;
+                    }
+                    match event {
+                        FlowEvent::User(UserControlEvent::Invoke(input)) => Some(Reply::Next(input)),
+                        FlowEvent::User(UserControlEvent::Answer(id, input)) => {
+                            Some(Reply::To(id, input))
+                        }
+                        _ => None,
+                    }
+                }
+
+                fn exec<F: ::core::future::Future>(
+                    &self,
+                    state: &#state_ty,
+                    future: ::core::pin::Pin<&mut F>,
+                    cx: &mut ::core::task::Context,
+                ) -> ::core::task::Poll<F::Output> {
+                    match ::flows::State::kind(state) {
+                        ::flows::FlowState::Running => ::core::future::Future::poll(future, cx),
+                        _ => ::core::task::Poll::Pending,
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,288 @@
+//! Step by step testing of flows
+//!
+//! `FlowTest` runs a flow function on a `SimRuntime` and polls it only when the test says so.
+//! In between polls the test sends control events, checks the state the flow settled in,
+//! reads what the function pushed and moves virtual time forward.
+//!
+//! ```ignore
+//! let mut test = FlowTest::new(example, ());
+//! assert_eq!(test.step(), FlowState::Running);
+//! test.send(UserControlEvent::Pause);
+//! assert_eq!(test.step(), FlowState::Paused);
+//! test.script(["yes"]).send(UserControlEvent::Resume).advance_ms(10_000);
+//! assert_eq!(test.take_pushed(), ["asked"]);
+//! assert_eq!(test.outcome(), Some(&FlowOutcome::Completed(())));
+//! ```
+
+use flows::runtime::sim::SimRuntime;
+use flows::{
+    ArcSlot, CommandError, Flow, FlowEvent, FlowEventHandler, FlowOutcome, FlowOutput, FlowState,
+    FnController, FnDataHandle, Handler, JOURNAL_CAPACITY, QueryId, Reply, State, UserControlEvent,
+    UserController, UserDataHandle,
+};
+use std::collections::VecDeque;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::task::{Context, Poll, Wake, Waker};
+
+/// The controller a function under test is handed
+pub type TestController<
+    U,
+    const CHAN_N: usize,
+    Q = (),
+    ST = FlowState,
+    H = FlowEventHandler,
+    const JOURNAL_N: usize = JOURNAL_CAPACITY,
+> = FnController<SimRuntime, U, CHAN_N, Q, ST, H, JOURNAL_N>;
+
+/// How a run of a function with output `T` ends
+pub type Outcome<T> = FlowOutcome<<T as FlowOutput>::Value, <T as FlowOutput>::Error>;
+
+type BoxedFlow<F, U, const CHAN_N: usize, Q, ST, H, const JOURNAL_N: usize> =
+    Pin<Box<Flow<F, U, CHAN_N, Q, ST, H, JOURNAL_N>>>;
+
+type Responder<Q, U> = Box<dyn FnMut(QueryId, Option<&Q>) -> Option<U>>;
+
+/// Set whenever the flow asks to be polled again
+struct Woken(AtomicBool);
+
+impl Wake for Woken {
+    fn wake(self: Arc<Self>) {
+        self.0.store(true, Ordering::Release)
+    }
+}
+
+/// A flow under test, polled only when the test says so
+pub struct FlowTest<
+    F: Future,
+    U: 'static,
+    UD: 'static,
+    FD: 'static,
+    const CHAN_N: usize,
+    const DATA_N: usize,
+    Q: 'static = (),
+    ST: State = FlowState,
+    H: 'static = FlowEventHandler,
+    const JOURNAL_N: usize = JOURNAL_CAPACITY,
+> where
+    F::Output: FlowOutput,
+{
+    runtime: SimRuntime,
+    flow: BoxedFlow<F, U, CHAN_N, Q, ST, H, JOURNAL_N>,
+    ctrl: UserController<U, CHAN_N, Q, ST, H, JOURNAL_N>,
+    data: UserDataHandle<UD, FD, DATA_N>,
+    woken: Arc<Woken>,
+    waker: Waker,
+    outcome: Option<Outcome<F::Output>>,
+    /// everything the function pushed that the test has not taken yet
+    pushed: Vec<FD>,
+    /// answers for the next queries, oldest query first
+    script: VecDeque<U>,
+    /// asked once the script ran out
+    responder: Option<Responder<Q, U>>,
+}
+
+impl<F, U, UD, FD, const CHAN_N: usize, const DATA_N: usize, Q, ST, H, const JOURNAL_N: usize>
+    FlowTest<F, U, UD, FD, CHAN_N, DATA_N, Q, ST, H, JOURNAL_N>
+where
+    F: Future,
+    F::Output: FlowOutput,
+    ST: State,
+    H: Handler<ST, FlowEvent<U>, Output = Reply<U>> + 'static,
+{
+    /// Set up `function` to run with `init` on a fresh `SimRuntime`
+    /// Nothing is polled yet, the first `step` starts the function
+    pub fn new<I>(
+        function: impl FnOnce(
+            I,
+            TestController<U, CHAN_N, Q, ST, H, JOURNAL_N>,
+            FnDataHandle<UD, FD, DATA_N>,
+        ) -> F,
+        init: I,
+    ) -> Self {
+        let runtime = SimRuntime::new();
+        let slot = ArcSlot::<U, UD, FD, CHAN_N, DATA_N, Q, ST, H, JOURNAL_N>::new();
+        let (fn_data, user_data) = slot.handles();
+        let (fn_ctrl, flow_ctrl, user_ctrl) = slot.ctrls(&runtime);
+        let flow = Flow::new(function(init, fn_ctrl, fn_data), flow_ctrl);
+        let woken = Arc::new(Woken(AtomicBool::new(true)));
+        Self {
+            runtime,
+            flow: Box::pin(flow),
+            ctrl: user_ctrl,
+            data: user_data,
+            waker: Waker::from(woken.clone()),
+            woken,
+            outcome: None,
+            pushed: Vec::new(),
+            script: VecDeque::new(),
+            responder: None,
+        }
+    }
+
+    /// Poll the flow once, whether or not it asked for it, and return the state it settled in
+    /// Does nothing once the flow has ended
+    pub fn step(&mut self) -> ST {
+        if self.outcome.is_none() {
+            self.woken.0.store(false, Ordering::Release);
+            let mut cx = Context::from_waker(&self.waker);
+            if let Poll::Ready(outcome) = self.flow.as_mut().poll(&mut cx) {
+                self.outcome = Some(outcome);
+            }
+            while let Some(data) = self.data.try_recv() {
+                self.pushed.push(data);
+            }
+        }
+        self.state()
+    }
+
+    /// Poll the flow for as long as it asks for it, answering its queries from the script
+    /// Time does not move, the flow is left waiting on a delay, the user or a pause
+    pub fn run(&mut self) -> ST {
+        while self.outcome.is_none() && self.woken.0.load(Ordering::Acquire) {
+            self.step();
+            self.answer_queries();
+        }
+        self.state()
+    }
+
+    /// Move virtual time forward, running the flow at every deadline it waits on along the way
+    pub fn advance_ms(&mut self, millis: u32) -> ST {
+        self.advance_us(millis as u64 * 1000)
+    }
+
+    pub fn advance_us(&mut self, micros: u64) -> ST {
+        let until = self.runtime.time_us().saturating_add(micros);
+        self.run();
+        while let Some(deadline) = self.runtime.next_deadline_us().filter(|&d| d <= until) {
+            self.runtime
+                .advance_us(deadline.saturating_sub(self.runtime.time_us()));
+            self.run();
+        }
+        self.runtime
+            .advance_us(until.saturating_sub(self.runtime.time_us()));
+        self.run()
+    }
+
+    /// Queue a control event for the flow, it is applied by the next poll
+    /// Panics if the control channel is full
+    #[track_caller]
+    pub fn send(&mut self, event: UserControlEvent<U>) -> &mut Self {
+        let command = match event {
+            UserControlEvent::Pause => self.ctrl.pause(),
+            UserControlEvent::Resume => self.ctrl.resume(),
+            UserControlEvent::Cancel => self.ctrl.cancel(),
+            UserControlEvent::Signal(name) => self.ctrl.signal(name),
+            UserControlEvent::Invoke(input) => {
+                self.ctrl.invoke(input).expect("could not invoke the flow");
+                return self;
+            }
+            UserControlEvent::Answer(id, input) => {
+                self.ctrl
+                    .answer(id, input)
+                    .expect("could not answer the query");
+                return self;
+            }
+        };
+        // the command resolves right away only if it could not be sent
+        let mut command = std::pin::pin!(command);
+        let mut cx = Context::from_waker(Waker::noop());
+        if let Poll::Ready(Err(CommandError::Full)) = command.as_mut().poll(&mut cx) {
+            panic!("control channel is full");
+        }
+        self
+    }
+
+    /// Answer the next queries of the function with `answers`, in order
+    pub fn script(&mut self, answers: impl IntoIterator<Item = U>) -> &mut Self {
+        self.script.extend(answers);
+        self
+    }
+
+    /// Answer the queries left once the script ran out with `responder`, `None` leaves one pending
+    pub fn respond_with(
+        &mut self,
+        responder: impl FnMut(QueryId, Option<&Q>) -> Option<U> + 'static,
+    ) -> &mut Self {
+        self.responder = Some(Box::new(responder));
+        self
+    }
+
+    /// Push data for the function to receive
+    /// Panics if the data queue is full
+    #[track_caller]
+    pub fn send_data(&mut self, data: UD) -> &mut Self {
+        if self.data.try_push(data).is_err() {
+            panic!("data queue is full");
+        }
+        self
+    }
+
+    /// Everything the function pushed since the last call, oldest first
+    pub fn take_pushed(&mut self) -> Vec<FD> {
+        std::mem::take(&mut self.pushed)
+    }
+
+    /// The state the flow settled in at its last poll
+    pub fn state(&self) -> ST {
+        self.ctrl.state()
+    }
+
+    /// Panics unless the flow settled in `expected` at its last poll
+    #[track_caller]
+    pub fn assert_state(&self, expected: ST) -> &Self {
+        assert_eq!(self.state(), expected, "unexpected flow state");
+        self
+    }
+
+    /// How the flow ended, `None` while it has not
+    pub fn outcome(&self) -> Option<&Outcome<F::Output>> {
+        self.outcome.as_ref()
+    }
+
+    /// The user side of the flow, e.g. to look at its queries
+    pub fn ctrl(&self) -> &UserController<U, CHAN_N, Q, ST, H, JOURNAL_N> {
+        &self.ctrl
+    }
+
+    /// The runtime the function runs on
+    pub fn runtime(&self) -> &SimRuntime {
+        &self.runtime
+    }
+
+    /// Virtual time in milliseconds
+    pub fn time_ms(&self) -> u64 {
+        self.runtime.time_us() / 1000
+    }
+
+    fn answer_queries(&mut self) {
+        let mut pending = Vec::new();
+        self.ctrl.for_each_query(|id, _| pending.push(id));
+        for id in pending {
+            let answer = match self.script.pop_front() {
+                Some(answer) => Some(answer),
+                None => {
+                    let Some(responder) = self.responder.as_mut() else {
+                        return;
+                    };
+                    let mut answer = None;
+                    self.ctrl.for_each_query(|query, prompt| {
+                        if query == id {
+                            answer = responder(query, prompt);
+                        }
+                    });
+                    answer
+                }
+            };
+            match answer {
+                Some(answer) => self
+                    .ctrl
+                    .answer(id, answer)
+                    .expect("could not answer the query"),
+                None => return,
+            }
+        }
+    }
+}
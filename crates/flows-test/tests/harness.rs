@@ -0,0 +1,157 @@
+use flows::{FlowOutcome, FlowState, FnDataHandle, UserControlEvent};
+use flows_test::{FlowTest, TestController};
+use std::future::{Future, poll_fn};
+use std::pin::Pin;
+use std::task::Poll;
+
+/// Counts up every 10ms until it is cancelled
+async fn ticker(
+    _init: (),
+    ctrl: TestController<(), 4>,
+    data: FnDataHandle<(), u32, 16>,
+) -> Result<(), ()> {
+    for tick in 0.. {
+        let _ = data.try_push(tick);
+        ctrl.delay_ms(10).await;
+    }
+    Ok(())
+}
+
+/// Asks for a name and pushes the greeting
+async fn greeter(
+    _init: (),
+    ctrl: TestController<String, 4, &'static str>,
+    data: FnDataHandle<(), String, 4>,
+) -> Result<(), ()> {
+    let name = ctrl.ask("name?").await;
+    data.push(format!("hello {name}")).await.map_err(|_| ())
+}
+
+/// Ticks every 10ms while it waits for a name
+async fn ticking_greeter(
+    _init: (),
+    ctrl: TestController<String, 4, &'static str>,
+    data: FnDataHandle<(), String, 16>,
+) -> Result<(), ()> {
+    let mut ask = ctrl.ask("name?");
+    let mut delay = Box::pin(ctrl.delay_ms(10));
+    let name = poll_fn(|cx| {
+        if let Poll::Ready(name) = Pin::new(&mut ask).poll(cx) {
+            return Poll::Ready(name);
+        }
+        while delay.as_mut().poll(cx).is_ready() {
+            let _ = data.try_push(String::from("tick"));
+            delay.set(ctrl.delay_ms(10));
+        }
+        Poll::Pending
+    })
+    .await;
+    data.push(format!("hello {name}")).await.map_err(|_| ())
+}
+
+/// Polls its ask again after it was answered
+async fn asks_once(
+    _init: (),
+    ctrl: TestController<String, 4, &'static str>,
+    data: FnDataHandle<(), String, 4>,
+) -> Result<(), ()> {
+    let mut ask = ctrl.ask("name?");
+    let name = (&mut ask).await;
+    let again = poll_fn(|cx| Poll::Ready(Pin::new(&mut ask).poll(cx))).await;
+    assert_eq!(again, Poll::Pending);
+    data.push(format!("hello {name}")).await.map_err(|_| ())
+}
+
+/// Waits `init` milliseconds before it completes
+async fn sleeper(
+    millis: u32,
+    ctrl: TestController<(), 4>,
+    data: FnDataHandle<(), &'static str, 4>,
+) -> Result<u32, ()> {
+    ctrl.delay_ms(millis).await;
+    let _ = data.try_push("woke");
+    Ok(millis)
+}
+
+#[test]
+fn pause_resume_cancel() {
+    let mut test = FlowTest::new(ticker, ());
+    assert_eq!(test.step(), FlowState::Running);
+    assert_eq!(test.take_pushed(), [0]);
+
+    test.send(UserControlEvent::Pause);
+    assert_eq!(test.step(), FlowState::Paused);
+    // time passes without the function
+    test.advance_ms(50);
+    test.assert_state(FlowState::Paused);
+    assert!(test.take_pushed().is_empty());
+
+    test.send(UserControlEvent::Resume);
+    assert_eq!(test.run(), FlowState::Running);
+    assert_eq!(test.take_pushed(), [1]);
+
+    test.send(UserControlEvent::Cancel);
+    assert_eq!(test.step(), FlowState::Cancelled);
+    assert_eq!(test.outcome(), Some(&FlowOutcome::Cancelled));
+}
+
+#[test]
+fn scripted_answers() {
+    let mut test = FlowTest::new(greeter, ());
+    test.script([String::from("ada")]);
+    assert_eq!(test.run(), FlowState::Completed);
+    assert_eq!(test.take_pushed(), ["hello ada"]);
+    assert_eq!(test.outcome(), Some(&FlowOutcome::Completed(())));
+}
+
+#[test]
+fn unanswered_query_blocks() {
+    let mut test = FlowTest::new(greeter, ());
+    test.respond_with(|_, _| None);
+    assert_eq!(test.run(), FlowState::Blocked);
+    let queries = test.ctrl().queries();
+    assert_eq!(queries.len(), 1);
+    assert_eq!(queries[0].prompt, Some("name?"));
+
+    test.send(UserControlEvent::Answer(
+        queries[0].id,
+        String::from("grace"),
+    ));
+    assert_eq!(test.run(), FlowState::Completed);
+    assert_eq!(test.take_pushed(), ["hello grace"]);
+}
+
+#[test]
+fn advance_through_delay() {
+    let mut test = FlowTest::new(sleeper, 100);
+    assert_eq!(test.advance_ms(99), FlowState::Running);
+    assert!(test.take_pushed().is_empty());
+    assert_eq!(test.outcome(), None);
+
+    assert_eq!(test.advance_ms(1), FlowState::Completed);
+    assert_eq!(test.time_ms(), 100);
+    assert_eq!(test.take_pushed(), ["woke"]);
+    assert_eq!(test.outcome(), Some(&FlowOutcome::Completed(100)));
+}
+
+#[test]
+fn unanswered_query_blocks_joined_work() {
+    let mut test = FlowTest::new(ticking_greeter, ());
+    test.respond_with(|_, _| None);
+    assert_eq!(test.run(), FlowState::Blocked);
+    // the ticks wait along with the query
+    assert_eq!(test.advance_ms(50), FlowState::Blocked);
+
+    test.send(UserControlEvent::Invoke(String::from("ada")));
+    assert_eq!(test.run(), FlowState::Completed);
+    assert_eq!(test.take_pushed(), ["hello ada"]);
+}
+
+#[test]
+fn answered_query_is_fused() {
+    let mut test = FlowTest::new(asks_once, ());
+    test.script([String::from("ada")]);
+    assert_eq!(test.run(), FlowState::Completed);
+    assert_eq!(test.take_pushed(), ["hello ada"]);
+    assert!(test.ctrl().queries().is_empty());
+}
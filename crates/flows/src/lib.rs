@@ -1 +1,2 @@
 pub use flows_core::*;
+pub use flows_macros::{flow, state_machine};
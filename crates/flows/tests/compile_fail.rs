@@ -0,0 +1,6 @@
+//! Machines the `state_machine!` macro has to reject at compile time
+
+#[test]
+fn state_machine() {
+    trybuild::TestCases::new().compile_fail("tests/ui/*.rs");
+}
@@ -0,0 +1,72 @@
+//! Launches functions turned into flows by `#[flows::flow]`
+
+use flows::runtime::sim::SimRuntime;
+use flows::{Done, FlowOutcome, FlowState, FnController, FnDataHandle, LaunchError, state_machine};
+
+state_machine! {
+    pub Gate {
+        states {
+            Open: Running,
+            Closed: Paused,
+            Shut: Cancelled,
+        }
+        transitions {
+            Open + Pause => Closed,
+            Closed + Resume => Open,
+            _ + Cancel => Shut,
+        }
+    }
+}
+
+/// Pushes `until` ticks, one every 10ms, and completes with how many it pushed
+#[flows::flow(chan = 4, data = 4)]
+async fn count(
+    until: u32,
+    ctrl: FnController<SimRuntime, ()>,
+    data: FnDataHandle<(), u32>,
+) -> Done<u32> {
+    for tick in 0..until {
+        let _ = data.try_push(tick);
+        ctrl.delay_ms(10).await;
+    }
+    Done(until)
+}
+
+/// Waits `millis`, on a slot with the `Gate` handler
+#[flows::flow(chan = 4, data = 4)]
+async fn gated(
+    millis: u32,
+    ctrl: FnController<SimRuntime, (), _, (), GateState, Gate>,
+    _data: FnDataHandle<(), ()>,
+) -> Done<u32> {
+    ctrl.delay_ms(millis).await;
+    Done(millis)
+}
+
+#[test]
+fn launch_one_run_at_a_time() {
+    let sim = SimRuntime::new();
+
+    let flow = count::launch(&sim, 3).unwrap();
+    assert!(matches!(count::launch(&sim, 1), Err(LaunchError::Busy)));
+    assert_eq!(sim.block_on(flow.join), Ok(FlowOutcome::Completed(3)));
+    assert_eq!(flow.ctrl.state(), FlowState::Completed);
+    assert_eq!(flow.data.try_recv(), Some(0));
+
+    // launched again once the run ended, on another runtime
+    let other = SimRuntime::new();
+    let flow = count::launch(&other, 1).unwrap();
+    assert_eq!(other.block_on(flow.join), Ok(FlowOutcome::Completed(1)));
+}
+
+#[test]
+fn launch_with_a_handler() {
+    let sim = SimRuntime::new();
+
+    let flow = gated::launch(&sim, 20).unwrap();
+    assert!(sim.block_on(flow.ctrl.pause()).is_ok());
+    assert_eq!(flow.ctrl.state(), GateState::Closed);
+    sim.advance_ms(50);
+    assert!(sim.block_on(flow.ctrl.resume()).is_ok());
+    assert_eq!(sim.block_on(flow.join), Ok(FlowOutcome::Completed(20)));
+}
@@ -0,0 +1,128 @@
+//! Expands a machine with `state_machine!` and runs flows on it
+
+use flows::runtime::FlowRuntime;
+use flows::runtime::sim::SimRuntime;
+use flows::{ArcSlot, FlowEvent, FlowState, FnControlEvent, FnController, FnDataHandle, State};
+use flows::{UserControlEvent, state_machine};
+
+state_machine! {
+    pub Throttle {
+        states {
+            Running: Running,
+            Throttled: Paused,
+            Waiting: Blocked,
+            Cancelled: Cancelled,
+        }
+        signals {
+            Slow = "slow",
+            Fast = "fast",
+        }
+        transitions {
+            Running + Slow => Throttled,
+            Throttled + Fast => Running,
+            Running + Block => Waiting,
+            Waiting + Invoke => Running,
+            Waiting + Answer => Running,
+            _ + Cancel => Cancelled,
+        }
+    }
+}
+
+type Ctrl = FnController<SimRuntime, u32, 4, (), ThrottleState, Throttle>;
+type Slot = ArcSlot<u32, (), u32, 4, 4, (), ThrottleState, Throttle>;
+
+/// Waits for a number, then pushes it once every 10ms, counting up
+async fn count(_init: (), ctrl: Ctrl, data: FnDataHandle<(), u32, 4>) -> Result<(), ()> {
+    let mut n = ctrl.block().await;
+    loop {
+        let _ = data.try_push(n);
+        n += 1;
+        ctrl.delay_ms(10).await;
+    }
+}
+
+/// Runs `count` as a child and keeps ticking on its own
+async fn parent(_init: (), ctrl: Ctrl, _data: FnDataHandle<(), u32, 4>) -> Result<(), ()> {
+    let _child = ctrl.spawn_child(count, ()).await?;
+    loop {
+        ctrl.delay_ms(10).await;
+    }
+}
+
+#[test]
+fn expansion() {
+    use ThrottleEvent as E;
+    use ThrottleState as S;
+
+    // terminal states that were not declared are added
+    assert_eq!(
+        S::from_kind(FlowState::Completed).kind(),
+        FlowState::Completed
+    );
+    assert_eq!(S::from_kind(FlowState::Error).kind(), FlowState::Error);
+    assert_eq!(S::from_kind(FlowState::Cancelled), S::Cancelled);
+    assert_eq!(S::from_kind(FlowState::Blocked), S::Waiting);
+    assert_eq!(S::default(), S::Running);
+    for state in [S::Running, S::Throttled, S::Waiting, S::Cancelled] {
+        assert_eq!(state.to_bits().and_then(S::from_bits), Some(state));
+    }
+
+    assert_eq!(Throttle::next(S::Running, E::Slow), S::Throttled);
+    assert_eq!(Throttle::next(S::Throttled, E::Fast), S::Running);
+    // no entry, the state stays
+    assert_eq!(Throttle::next(S::Throttled, E::Slow), S::Throttled);
+    // `_` applies to every non-terminal state
+    assert_eq!(Throttle::next(S::Waiting, E::Cancel), S::Cancelled);
+    assert_eq!(Throttle::next(S::Cancelled, E::Resume), S::Cancelled);
+    assert!(Throttle::TRANSITIONS.contains(&(S::Throttled, E::Cancel, S::Cancelled)));
+
+    assert_eq!(
+        E::of::<u32>(&FlowEvent::User(UserControlEvent::Signal("slow"))),
+        Some(E::Slow)
+    );
+    assert_eq!(
+        E::of::<u32>(&FlowEvent::Fn(FnControlEvent::Block)),
+        Some(E::Block)
+    );
+    assert_eq!(
+        E::of::<u32>(&FlowEvent::User(UserControlEvent::Signal("other"))),
+        None
+    );
+}
+
+#[test]
+fn runs_a_flow() {
+    let sim = SimRuntime::new();
+    let mut flow = sim.spawn_flow(count, (), Slot::new()).unwrap();
+    sim.run_until_stalled();
+    assert_eq!(flow.state(), ThrottleState::Waiting);
+
+    flow.ctrl().invoke(1).unwrap();
+    sim.run_until_stalled();
+    assert_eq!(flow.state(), ThrottleState::Running);
+    assert_eq!(flow.data().try_recv(), Some(1));
+
+    // the function is not polled while throttled
+    assert!(sim.block_on(flow.ctrl().signal("slow")).is_ok());
+    assert_eq!(flow.state(), ThrottleState::Throttled);
+    sim.advance_ms(50);
+    assert_eq!(flow.data().try_recv(), None);
+
+    assert!(sim.block_on(flow.ctrl().signal("fast")).is_ok());
+    assert_eq!(flow.state(), ThrottleState::Running);
+    sim.run_until_stalled();
+    assert_eq!(flow.data().try_recv(), Some(2));
+
+    assert!(sim.block_on(flow.cancel()).is_ok());
+    assert_eq!(flow.state(), ThrottleState::Cancelled);
+}
+
+#[test]
+fn shows_a_blocked_child() {
+    let sim = SimRuntime::new();
+    let flow = sim.spawn_flow(parent, (), Slot::new()).unwrap();
+    sim.run_until_stalled();
+    // running itself, but waiting on its child
+    assert_eq!(flow.state(), ThrottleState::Waiting);
+    assert!(flow.ctrl().blocked_path().is_some());
+}
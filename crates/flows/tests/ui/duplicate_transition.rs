@@ -0,0 +1,15 @@
+flows::state_machine! {
+    Twice {
+        states {
+            Running: Running,
+            Paused: Paused,
+        }
+        transitions {
+            Running + Pause => Paused,
+            Running + Pause => Running,
+            Paused + Resume => Running,
+        }
+    }
+}
+
+fn main() {}
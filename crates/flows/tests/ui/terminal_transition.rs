@@ -0,0 +1,14 @@
+flows::state_machine! {
+    Undead {
+        states {
+            Running: Running,
+            Cancelled: Cancelled,
+        }
+        transitions {
+            Running + Cancel => Cancelled,
+            Cancelled + Resume => Running,
+        }
+    }
+}
+
+fn main() {}
@@ -0,0 +1,16 @@
+flows::state_machine! {
+    Island {
+        states {
+            Running: Running,
+            Paused: Paused,
+            Stranded: Paused,
+        }
+        transitions {
+            Running + Pause => Paused,
+            Paused + Resume => Running,
+            Stranded + Resume => Running,
+        }
+    }
+}
+
+fn main() {}
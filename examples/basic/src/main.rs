@@ -1,12 +1,10 @@
 use flows::runtime::tokio::TokioRuntime;
 
-const CHANNEL_SIZE: usize = 8;
-const DATA_CHANNEL_SIZE: usize = 16;
-
+#[flows::flow(chan = 8, data = 16)]
 async fn example(
     _init: (),
-    _ctrl: flows::FnController<TokioRuntime, (), CHANNEL_SIZE>,
-    _data: flows::FnDataHandle<(), (), DATA_CHANNEL_SIZE>,
+    ctrl: flows::FnController<TokioRuntime, ()>,
+    _data: flows::FnDataHandle<(), ()>,
 ) -> () {
     println!("Task: Starting interactive workflow");
 
@@ -15,33 +13,39 @@ async fn example(
         tokio::time::sleep(std::time::Duration::from_millis(2000)).await;
     }
 
+    println!("Task: Waiting for the user to confirm");
+    ctrl.block().await;
+
     println!("Workflow completed successfully!");
 }
 
-static RUNTIME: std::sync::LazyLock<TokioRuntime> =
-    std::sync::LazyLock::new(|| TokioRuntime::new());
-
-static SLOT_1: std::sync::LazyLock<flows::Slot<(), (), (), CHANNEL_SIZE, DATA_CHANNEL_SIZE>> =
-    std::sync::LazyLock::new(|| flows::Slot::default());
-
 #[tokio::main]
 async fn main() {
     println!("Enhanced Pausable Futures Demo (Tokio Runtime)");
     println!("===============================================");
 
-    let slot = &*SLOT_1;
-    let runtime = &*RUNTIME;
-
-    let (fn_data_handle, user_data_handle) = slot.handles();
-    let (fn_ctrl, flow_func_ctrl, user_ctrl) = slot.ctrls(runtime);
+    let flows::Launched {
+        ctrl: user_ctrl,
+        join,
+        ..
+    } = example::launch(&TokioRuntime::new(), ()).expect("could not launch the flow");
 
-    let future = example((), fn_ctrl, fn_data_handle);
-    let flow = flows::Flow::new(future, flow_func_ctrl);
-
-    let handle = tokio::spawn(flow);
     tokio::time::sleep(std::time::Duration::from_millis(3500)).await;
-    user_ctrl.pause();
+    match user_ctrl.pause().await {
+        Ok(()) => println!("Main: flow paused"),
+        Err(e) => println!("Main: could not pause the flow: {}", e),
+    }
     tokio::time::sleep(std::time::Duration::from_millis(10000)).await;
-    user_ctrl.resume();
-    handle.await;
+
+    let mut changes = user_ctrl.state_changes();
+    if let Err(e) = user_ctrl.resume().await {
+        println!("Main: could not resume the flow: {}", e);
+    }
+    while let Some(state) = changes.next().await {
+        println!("Main: flow is {:?}", state);
+        if state == flows::FlowState::Blocked {
+            let _ = user_ctrl.invoke(());
+        }
+    }
+    println!("Flow ended: {:?}", join.await);
 }
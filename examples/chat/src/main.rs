@@ -4,16 +4,13 @@ use async_openai::{Client, config::OpenAIConfig};
 use flows::runtime::tokio::TokioRuntime;
 use futures::StreamExt;
 use std::io::{self, Write};
-use std::sync::LazyLock;
 use tokio::io::{AsyncBufReadExt, BufReader, Stdin};
 
-const CHANNEL_SIZE: usize = 8;
-const DATA_CHANNEL_SIZE: usize = 16;
-
+#[flows::flow(chan = 8, data = 16)]
 async fn example(
     init: (Client<OpenAIConfig>, String),
-    ctrl: flows::FnController<TokioRuntime, String, CHANNEL_SIZE>,
-    data: flows::FnDataHandle<(), String, DATA_CHANNEL_SIZE>,
+    _ctrl: flows::FnController<TokioRuntime, String>,
+    data: flows::FnDataHandle<(), String>,
 ) -> Result<()> {
     let messages = vec![
         async_openai::types::ChatCompletionRequestSystemMessageArgs::default()
@@ -38,15 +35,12 @@ async fn example(
     while let Some(chunk) = stream.next().await {
         match chunk {
             Ok(chunk) => {
-                chunk.choices.iter().for_each(|chat_choice| {
-                    let content = chat_choice.delta.content.clone();
-                    match content {
-                        Some(text) => {
-                            print!("{}", text);
-                        }
-                        _ => {}
+                for chat_choice in chunk.choices {
+                    if let Some(text) = chat_choice.delta.content {
+                        // waits while the user side is behind instead of dropping tokens
+                        let _ = data.push(text).await;
                     }
-                });
+                }
             }
             Err(e) => {
                 eprintln!("Error receiving chunk: {}", e);
@@ -57,13 +51,6 @@ async fn example(
     Ok(())
 }
 
-static SLOT_1: std::sync::LazyLock<
-    flows::Slot<String, (), String, CHANNEL_SIZE, DATA_CHANNEL_SIZE>,
-> = std::sync::LazyLock::new(|| flows::Slot::default());
-
-static RUNTIME: std::sync::LazyLock<TokioRuntime> =
-    std::sync::LazyLock::new(|| TokioRuntime::new());
-
 async fn prompt_user(stdin: Stdin) -> Option<String> {
     let mut reader = BufReader::new(stdin);
     let mut input = String::new();
@@ -90,12 +77,6 @@ async fn prompt_user(stdin: Stdin) -> Option<String> {
 
 #[tokio::main]
 async fn main() {
-    let slot = &*SLOT_1;
-    let runtime = &*RUNTIME;
-
-    let (fn_data_handle, user_data_handle) = slot.handles();
-    let (fn_ctrl, flow_func_ctrl, user_ctrl) = slot.ctrls(runtime);
-
     let api_key = std::env::var("GROQ_API_KEY").expect("GROQ_API_KEY environment variable not set");
 
     let config = OpenAIConfig::default()
@@ -108,14 +89,16 @@ async fn main() {
 
     let init_message = prompt_user(stdin).await.unwrap();
 
-    let future = example((client, init_message), fn_ctrl, fn_data_handle);
-    let flow = flows::Flow::new(future, flow_func_ctrl);
-
-    let handle = tokio::spawn(flow);
-    handle.await;
-    // loop {
-    //     while let Some(incoming) = user_data_handle.recv() {
-    //         print!("{}", incoming);
-    //     }
-    // }
+    let flows::Launched { mut data, join, .. } =
+        example::launch(&TokioRuntime::new(), (client, init_message))
+            .expect("could not launch the chat");
+    // ends once the flow does
+    while let Some(text) = data.next().await {
+        print!("{}", text);
+        io::stdout().flush().unwrap();
+    }
+    println!();
+    if let Ok(flows::FlowOutcome::Failed(e)) = join.await {
+        eprintln!("Chat failed: {}", e);
+    }
 }